@@ -0,0 +1,299 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Derive macros for the p9ds wire types.
+//!
+//! `WireSize` implements `ispf::WireSize` for a struct by summing the encoded
+//! size of each field, which keeps the size in step with the serde layout.
+//! Fields are sized as follows.
+//!
+//! - Integers are their in memory size.
+//! - Fields with `#[serde(with = "ispf::str_lv*")]` or
+//!   `#[serde(with = "ispf::vec_lv*")]` are their length prefix plus their
+//!   contents.
+//! - Any other field is sized by its own `ispf::WireSize` implementation.
+//!
+//! On a fieldless enum `WireSize` is the size of its representation.
+//!
+//! `P9Message` additionally implements `p9ds::proto::Message` and adds a
+//! `sized` method that fills in the leading `size` field of the message. The
+//! message type is taken from the struct name unless it is given with
+//! `#[p9(message_type = "...")]`. A T-message names its reply with
+//! `#[p9(response = "...")]`, which implements `p9ds::proto::Request`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Field, Fields,
+    GenericArgument, Ident, Lit, Meta, NestedMeta, PathArguments, Type,
+};
+
+#[proc_macro_derive(WireSize)]
+pub fn derive_wire_size(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match wire_size(&input) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(P9Message, attributes(p9))]
+pub fn derive_p9_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let result = wire_size(&input).and_then(|size| {
+        let message = message(&input)?;
+        Ok(quote! {
+            #size
+            #message
+        })
+    });
+    match result {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn wire_size(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(s) => {
+            let mut sizes = Vec::new();
+            for (i, f) in s.fields.iter().enumerate() {
+                let access = match &f.ident {
+                    Some(ident) => quote!(self.#ident),
+                    None => {
+                        let index = syn::Index::from(i);
+                        quote!(self.#index)
+                    }
+                };
+                sizes.push(field_size(f, access)?);
+            }
+            quote!(0 #(+ #sizes)*)
+        }
+        Data::Enum(e) if e.variants.iter().all(|v| v.fields.is_empty()) => {
+            quote!(::std::mem::size_of::<Self>())
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "WireSize requires a struct or a fieldless enum",
+            ))
+        }
+    };
+    Ok(quote! {
+        impl ::ispf::WireSize for #name {
+            fn wire_size(&self) -> usize {
+                #body
+            }
+        }
+    })
+}
+
+fn message(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(fields) => {
+                for header in ["size", "typ", "tag"] {
+                    if !fields
+                        .named
+                        .iter()
+                        .any(|f| f.ident.as_ref().unwrap() == header)
+                    {
+                        return Err(syn::Error::new_spanned(
+                            input,
+                            format!("P9Message requires a `{}` field", header),
+                        ));
+                    }
+                }
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "P9Message requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "P9Message can only be derived for structs",
+            ))
+        }
+    }
+
+    let attrs = p9_attrs(&input.attrs)?;
+    let message_type = match attrs.message_type {
+        Some(mt) => mt,
+        None => name.clone(),
+    };
+    let request = attrs.response.map(|response| {
+        quote! {
+            impl p9ds::proto::Request for #name {
+                type Response = #response;
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl p9ds::proto::Message for #name {
+            fn instance_type(&self) -> p9ds::proto::MessageType {
+                self.typ
+            }
+            fn message_type() -> p9ds::proto::MessageType {
+                p9ds::proto::MessageType::#message_type
+            }
+        }
+
+        impl #name {
+            /// Set the size field to the encoded length of the message.
+            pub fn sized(mut self) -> Self {
+                self.size = ::ispf::WireSize::wire_size(&self) as u32;
+                self
+            }
+        }
+
+        #request
+    })
+}
+
+#[derive(Default)]
+struct P9Attrs {
+    message_type: Option<Ident>,
+    response: Option<Ident>,
+}
+
+/// Parse `#[p9(message_type = "...", response = "...")]`.
+fn p9_attrs(attrs: &[Attribute]) -> syn::Result<P9Attrs> {
+    let mut p9 = P9Attrs::default();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("p9")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            m => return Err(syn::Error::new_spanned(m, "expected p9(...)")),
+        };
+        for nested in list.nested {
+            let nv = match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) => nv,
+                n => {
+                    return Err(syn::Error::new_spanned(
+                        n,
+                        "unknown p9 attribute",
+                    ))
+                }
+            };
+            let value = if nv.path.is_ident("message_type") {
+                &mut p9.message_type
+            } else if nv.path.is_ident("response") {
+                &mut p9.response
+            } else {
+                return Err(syn::Error::new_spanned(
+                    nv.path,
+                    "unknown p9 attribute",
+                ));
+            };
+            match nv.lit {
+                Lit::Str(s) => *value = Some(s.parse()?),
+                lit => {
+                    return Err(syn::Error::new_spanned(
+                        lit,
+                        "expected a string",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(p9)
+}
+
+/// Find the module named by `#[serde(with = "...")]`, if any.
+fn serde_with(field: &Field) -> syn::Result<Option<String>> {
+    for attr in field.attrs.iter().filter(|a| a.path.is_ident("serde")) {
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested {
+                if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+                    if let (true, Lit::Str(s)) =
+                        (nv.path.is_ident("with"), &nv.lit)
+                    {
+                        return Ok(Some(s.value()));
+                    }
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn field_size(
+    field: &Field,
+    access: TokenStream2,
+) -> syn::Result<TokenStream2> {
+    let ty = &field.ty;
+    let with = match serde_with(field)? {
+        Some(with) => with,
+        None if is_integer(ty) => {
+            return Ok(quote!(::std::mem::size_of::<#ty>()))
+        }
+        None => return Ok(quote!(::ispf::WireSize::wire_size(&#access))),
+    };
+
+    let prefix = match with.as_str() {
+        "ispf::str_lv8" | "ispf::vec_lv8" => quote!(u8),
+        "ispf::str_lv16" | "ispf::vec_lv16" => quote!(u16),
+        "ispf::str_lv32" | "ispf::vec_lv32" | "ispf::vec_lv32b" => {
+            quote!(u32)
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                field,
+                format!("can't size a field serialized with {}", with),
+            ))
+        }
+    };
+
+    let contents = if with.starts_with("ispf::str_") {
+        quote!(#access.len())
+    } else {
+        match vec_element(ty) {
+            Some(elem) if is_integer(elem) => {
+                quote!(#access.len() * ::std::mem::size_of::<#elem>())
+            }
+            _ => quote! {
+                #access.iter().map(::ispf::WireSize::wire_size).sum::<usize>()
+            },
+        }
+    };
+    Ok(quote!(::std::mem::size_of::<#prefix>() + #contents))
+}
+
+fn is_integer(ty: &Type) -> bool {
+    match ty {
+        // Types passed through macro_rules! arrive wrapped in a group.
+        Type::Group(g) => is_integer(&g.elem),
+        Type::Path(p) => ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"]
+            .iter()
+            .any(|int| p.path.is_ident(int)),
+        _ => false,
+    }
+}
+
+/// The element type of a `Vec<T>`.
+fn vec_element(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Vec" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(t) => Some(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Server side authentication hooks.
+//!
+//! A server that wants to gate attaches answers Tauth by creating an
+//! `AuthHandler` session for the new afid, routes Tread and Twrite requests on
+//! that afid to the session, and only accepts a Tattach naming the afid if the
+//! handler verifies the session. Errors are Linux errno values, ready to be
+//! returned in an Rlerror.
+
+use crate::errno::Errno;
+
+pub trait AuthHandler: Send + Sync {
+    type Session: Send;
+
+    /// Begin an authentication session for a Tauth from `uname` for the file
+    /// tree `aname`.
+    fn start(
+        &self,
+        uname: &str,
+        aname: &str,
+        n_uname: u32,
+    ) -> Result<Self::Session, u32>;
+
+    /// Handle a Twrite to the afid, returning the number of bytes consumed.
+    fn write(
+        &self,
+        session: &mut Self::Session,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u32, u32>;
+
+    /// Handle a Tread from the afid.
+    fn read(
+        &self,
+        session: &mut Self::Session,
+        offset: u64,
+        count: u32,
+    ) -> Result<Vec<u8>, u32>;
+
+    /// Decide whether a Tattach presenting the afid of `session` may proceed.
+    fn verify(&self, session: &Self::Session, uname: &str, aname: &str)
+        -> bool;
+}
+
+/// Accepts clients that write a secret shared with the server to the afid.
+pub struct SharedSecret {
+    secret: Vec<u8>,
+}
+
+impl SharedSecret {
+    pub fn new(secret: Vec<u8>) -> Self {
+        SharedSecret { secret }
+    }
+}
+
+impl AuthHandler for SharedSecret {
+    type Session = Vec<u8>;
+
+    fn start(&self, _: &str, _: &str, _: u32) -> Result<Vec<u8>, u32> {
+        Ok(Vec::new())
+    }
+
+    fn write(
+        &self,
+        session: &mut Vec<u8>,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u32, u32> {
+        // Writes must follow on from each other, which also keeps a huge
+        // offset from overflowing the end.
+        if offset != session.len() as u64
+            || session.len() + data.len() > self.secret.len()
+        {
+            return Err(Errno::EACCES.linux());
+        }
+        session.extend_from_slice(data);
+        Ok(data.len() as u32)
+    }
+
+    fn read(&self, _: &mut Vec<u8>, _: u64, _: u32) -> Result<Vec<u8>, u32> {
+        Ok(Vec::new())
+    }
+
+    fn verify(&self, session: &Vec<u8>, _: &str, _: &str) -> bool {
+        // Compare in constant time so the secret can't be guessed
+        // incrementally.
+        session.len() == self.secret.len()
+            && session
+                .iter()
+                .zip(self.secret.iter())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
@@ -0,0 +1,111 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Message framing for stream transports.
+//!
+//! Every 9P message starts with its total length as a little endian u32, so
+//! a byte stream is split into messages by reading the length and then
+//! exactly that many bytes. Messages may arrive split across reads or
+//! several to a read, which the codec takes care of by buffering.
+
+use crate::error::P9Error;
+use bytes::{Buf, BytesMut};
+use serde::Serialize;
+use std::mem::size_of;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// size[4] type[1] tag[2]
+pub const HEADER_SIZE: usize = 7;
+
+/// The message size limit used until a connection has negotiated one.
+pub const DEFAULT_MSIZE: u32 = 0x10000;
+
+/// The tag of an encoded message.
+pub fn frame_tag(frame: &[u8]) -> u16 {
+    u16::from_le_bytes([frame[5], frame[6]])
+}
+
+/// Set the tag of an encoded message.
+pub fn set_frame_tag(frame: &mut [u8], tag: u16) {
+    frame[5..HEADER_SIZE].copy_from_slice(&tag.to_le_bytes());
+}
+
+/// Frames 9P messages on a byte stream. Decoded frames are complete messages,
+/// including the size field. Messages larger than the negotiated msize are
+/// rejected in both directions.
+#[derive(Debug, Clone)]
+pub struct P9Codec {
+    msize: u32,
+}
+
+impl P9Codec {
+    pub fn new(msize: u32) -> Self {
+        P9Codec { msize }
+    }
+
+    pub fn msize(&self) -> u32 {
+        self.msize
+    }
+
+    /// Change the message size limit, typically to the msize agreed on by
+    /// Tversion and Rversion.
+    pub fn set_msize(&mut self, msize: u32) {
+        self.msize = msize;
+    }
+}
+
+impl Default for P9Codec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MSIZE)
+    }
+}
+
+impl Decoder for P9Codec {
+    type Item = BytesMut;
+    type Error = P9Error;
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<BytesMut>, P9Error> {
+        if src.len() < size_of::<u32>() {
+            return Ok(None);
+        }
+        let size = (&src[..size_of::<u32>()]).get_u32_le();
+        if (size as usize) < HEADER_SIZE {
+            return Err(P9Error::ShortMessage(size as usize));
+        }
+        if size > self.msize {
+            return Err(P9Error::MessageTooLarge {
+                size: size as usize,
+                msize: self.msize,
+            });
+        }
+
+        let size = size as usize;
+        if src.len() < size {
+            src.reserve(size - src.len());
+            return Ok(None);
+        }
+        Ok(Some(src.split_to(size)))
+    }
+}
+
+impl<T: Serialize> Encoder<&T> for P9Codec {
+    type Error = P9Error;
+
+    fn encode(&mut self, item: &T, dst: &mut BytesMut) -> Result<(), P9Error> {
+        let out = ispf::to_bytes_le(item)?;
+        if out.len() > self.msize as usize {
+            return Err(P9Error::MessageTooLarge {
+                size: out.len(),
+                msize: self.msize,
+            });
+        }
+        dst.extend_from_slice(out.as_slice());
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Linux errno values as carried by Rlerror.
+//!
+//! 9P2000.L always puts Linux errno numbers on the wire, whatever the host
+//! operating system of the client or server. Those numbers only coincide
+//! with the host's own errno values on Linux, so errors have to be
+//! translated on the way in and out. On illumos, for instance, Linux EDEADLK
+//! (35) is the host's ENOMSG.
+
+use crate::proto::Rlerror;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+macro_rules! errno {
+    ($($name:ident = $value:literal => $desc:literal,)*) => {
+        /// A Linux errno value.
+        #[allow(clippy::upper_case_acronyms)]
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            Hash,
+            TryFromPrimitive,
+            IntoPrimitive,
+        )]
+        #[repr(u32)]
+        pub enum Errno {
+            $($name = $value,)*
+        }
+
+        impl Errno {
+            /// The errno value for this error on the host.
+            pub fn host(self) -> i32 {
+                match self {
+                    $(Errno::$name => libc::$name,)*
+                }
+            }
+
+            /// Translate an errno value of the host. Returns `None` for host
+            /// errors that have no Linux equivalent here.
+            pub fn from_host(code: i32) -> Option<Errno> {
+                $(if code == libc::$name {
+                    return Some(Errno::$name);
+                })*
+                // Distinct from EOPNOTSUPP on some hosts, but not on Linux.
+                if code == libc::ENOTSUP {
+                    return Some(Errno::EOPNOTSUPP);
+                }
+                None
+            }
+
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(Errno::$name => $desc,)*
+                }
+            }
+        }
+    };
+}
+
+errno! {
+    EPERM = 1 => "Operation not permitted",
+    ENOENT = 2 => "No such file or directory",
+    ESRCH = 3 => "No such process",
+    EINTR = 4 => "Interrupted system call",
+    EIO = 5 => "Input/output error",
+    ENXIO = 6 => "No such device or address",
+    E2BIG = 7 => "Argument list too long",
+    ENOEXEC = 8 => "Exec format error",
+    EBADF = 9 => "Bad file descriptor",
+    ECHILD = 10 => "No child processes",
+    EAGAIN = 11 => "Resource temporarily unavailable",
+    ENOMEM = 12 => "Cannot allocate memory",
+    EACCES = 13 => "Permission denied",
+    EFAULT = 14 => "Bad address",
+    ENOTBLK = 15 => "Block device required",
+    EBUSY = 16 => "Device or resource busy",
+    EEXIST = 17 => "File exists",
+    EXDEV = 18 => "Invalid cross-device link",
+    ENODEV = 19 => "No such device",
+    ENOTDIR = 20 => "Not a directory",
+    EISDIR = 21 => "Is a directory",
+    EINVAL = 22 => "Invalid argument",
+    ENFILE = 23 => "Too many open files in system",
+    EMFILE = 24 => "Too many open files",
+    ENOTTY = 25 => "Inappropriate ioctl for device",
+    ETXTBSY = 26 => "Text file busy",
+    EFBIG = 27 => "File too large",
+    ENOSPC = 28 => "No space left on device",
+    ESPIPE = 29 => "Illegal seek",
+    EROFS = 30 => "Read-only file system",
+    EMLINK = 31 => "Too many links",
+    EPIPE = 32 => "Broken pipe",
+    EDOM = 33 => "Numerical argument out of domain",
+    ERANGE = 34 => "Numerical result out of range",
+    EDEADLK = 35 => "Resource deadlock avoided",
+    ENAMETOOLONG = 36 => "File name too long",
+    ENOLCK = 37 => "No locks available",
+    ENOSYS = 38 => "Function not implemented",
+    ENOTEMPTY = 39 => "Directory not empty",
+    ELOOP = 40 => "Too many levels of symbolic links",
+    ENOMSG = 42 => "No message of desired type",
+    EIDRM = 43 => "Identifier removed",
+    ENOSTR = 60 => "Device not a stream",
+    ENODATA = 61 => "No data available",
+    ETIME = 62 => "Timer expired",
+    ENOSR = 63 => "Out of streams resources",
+    ENOLINK = 67 => "Link has been severed",
+    EPROTO = 71 => "Protocol error",
+    EMULTIHOP = 72 => "Multihop attempted",
+    EBADMSG = 74 => "Bad message",
+    EOVERFLOW = 75 => "Value too large for defined data type",
+    EILSEQ = 84 => "Invalid or incomplete multibyte or wide character",
+    EUSERS = 87 => "Too many users",
+    ENOTSOCK = 88 => "Socket operation on non-socket",
+    EDESTADDRREQ = 89 => "Destination address required",
+    EMSGSIZE = 90 => "Message too long",
+    EPROTOTYPE = 91 => "Protocol wrong type for socket",
+    ENOPROTOOPT = 92 => "Protocol not available",
+    EPROTONOSUPPORT = 93 => "Protocol not supported",
+    ESOCKTNOSUPPORT = 94 => "Socket type not supported",
+    EOPNOTSUPP = 95 => "Operation not supported",
+    EPFNOSUPPORT = 96 => "Protocol family not supported",
+    EAFNOSUPPORT = 97 => "Address family not supported by protocol",
+    EADDRINUSE = 98 => "Address already in use",
+    EADDRNOTAVAIL = 99 => "Cannot assign requested address",
+    ENETDOWN = 100 => "Network is down",
+    ENETUNREACH = 101 => "Network is unreachable",
+    ENETRESET = 102 => "Network dropped connection on reset",
+    ECONNABORTED = 103 => "Software caused connection abort",
+    ECONNRESET = 104 => "Connection reset by peer",
+    ENOBUFS = 105 => "No buffer space available",
+    EISCONN = 106 => "Transport endpoint is already connected",
+    ENOTCONN = 107 => "Transport endpoint is not connected",
+    ESHUTDOWN = 108 => "Cannot send after transport endpoint shutdown",
+    ETOOMANYREFS = 109 => "Too many references: cannot splice",
+    ETIMEDOUT = 110 => "Connection timed out",
+    ECONNREFUSED = 111 => "Connection refused",
+    EHOSTDOWN = 112 => "Host is down",
+    EHOSTUNREACH = 113 => "No route to host",
+    EALREADY = 114 => "Operation already in progress",
+    EINPROGRESS = 115 => "Operation now in progress",
+    ESTALE = 116 => "Stale file handle",
+    EDQUOT = 122 => "Disk quota exceeded",
+    ECANCELED = 125 => "Operation canceled",
+    EOWNERDEAD = 130 => "Owner died",
+    ENOTRECOVERABLE = 131 => "State not recoverable",
+}
+
+impl Errno {
+    /// Interpret an errno value received on the wire.
+    pub fn from_linux(code: u32) -> Option<Errno> {
+        Errno::try_from(code).ok()
+    }
+
+    /// The errno value to put on the wire.
+    pub fn linux(self) -> u32 {
+        self.into()
+    }
+
+    pub fn kind(self) -> io::ErrorKind {
+        use io::ErrorKind::*;
+        match self {
+            Errno::ENOENT => NotFound,
+            Errno::EPERM | Errno::EACCES => PermissionDenied,
+            Errno::ECONNREFUSED => ConnectionRefused,
+            Errno::ECONNRESET => ConnectionReset,
+            Errno::ECONNABORTED => ConnectionAborted,
+            Errno::ENOTCONN => NotConnected,
+            Errno::EADDRINUSE => AddrInUse,
+            Errno::EADDRNOTAVAIL => AddrNotAvailable,
+            Errno::EPIPE => BrokenPipe,
+            Errno::EEXIST => AlreadyExists,
+            Errno::EAGAIN => WouldBlock,
+            Errno::EINVAL => InvalidInput,
+            Errno::ETIMEDOUT => TimedOut,
+            Errno::EINTR => Interrupted,
+            Errno::ENOSYS | Errno::EOPNOTSUPP => Unsupported,
+            Errno::ENOMEM => OutOfMemory,
+            _ => Other,
+        }
+    }
+
+    /// The closest errno for an error that carries no errno of its own.
+    pub fn from_kind(kind: io::ErrorKind) -> Errno {
+        use io::ErrorKind::*;
+        match kind {
+            NotFound => Errno::ENOENT,
+            PermissionDenied => Errno::EACCES,
+            ConnectionRefused => Errno::ECONNREFUSED,
+            ConnectionReset => Errno::ECONNRESET,
+            ConnectionAborted => Errno::ECONNABORTED,
+            NotConnected => Errno::ENOTCONN,
+            AddrInUse => Errno::EADDRINUSE,
+            AddrNotAvailable => Errno::EADDRNOTAVAIL,
+            BrokenPipe => Errno::EPIPE,
+            AlreadyExists => Errno::EEXIST,
+            WouldBlock => Errno::EAGAIN,
+            InvalidInput | InvalidData => Errno::EINVAL,
+            TimedOut => Errno::ETIMEDOUT,
+            Interrupted => Errno::EINTR,
+            Unsupported => Errno::EOPNOTSUPP,
+            OutOfMemory => Errno::ENOMEM,
+            _ => Errno::EIO,
+        }
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl std::error::Error for Errno {}
+
+impl From<&io::Error> for Errno {
+    fn from(e: &io::Error) -> Self {
+        e.raw_os_error()
+            .and_then(Errno::from_host)
+            .unwrap_or_else(|| Errno::from_kind(e.kind()))
+    }
+}
+
+impl From<io::Error> for Errno {
+    fn from(e: io::Error) -> Self {
+        Errno::from(&e)
+    }
+}
+
+impl From<Errno> for io::Error {
+    fn from(e: Errno) -> Self {
+        io::Error::from_raw_os_error(e.host())
+    }
+}
+
+impl From<Errno> for io::ErrorKind {
+    fn from(e: Errno) -> Self {
+        e.kind()
+    }
+}
+
+impl From<Errno> for Rlerror {
+    fn from(e: Errno) -> Self {
+        Rlerror::new(e.linux())
+    }
+}
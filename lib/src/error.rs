@@ -4,15 +4,81 @@
 
 // Copyright 2022 Oxide Computer Company
 
-use crate::proto::{MessageType, Rlerror};
+use crate::errno::Errno;
+use crate::proto::{LockStatus, MessageType};
+use std::io;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum P9Error {
+    // Transport and framing ~~~
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("not connected")]
+    NotConnected,
+    #[error("message too short ({0} bytes)")]
+    ShortMessage(usize),
+    #[error("message of {size} bytes exceeds msize {msize}")]
+    MessageTooLarge { size: usize, msize: u32 },
+    #[error("unknown message type {0}")]
+    UnknownMessageType(u8),
+    #[error("decode error: {0}")]
+    Decode(#[from] ispf::Error),
+
+    // Server errors ~~~
+    #[error("server error: {0}")]
+    Server(Errno),
+    #[error("server error: unknown errno {0}")]
+    UnknownServerErrno(u32),
+    #[error("server error: {0}")]
+    ServerMessage(String),
+    #[error("lock request failed: {0:?}")]
+    Lock(LockStatus),
+
+    // Protocol violations ~~~
     #[error("expected {0} type found {1}")]
     UnexpectedReturnType(MessageType, MessageType),
-    #[error("server error: {0}")]
-    ServerError(Rlerror, String),
-    #[error("error: {0}")]
-    General(String),
+    #[error("expected tag {expected} found {found}")]
+    TagMismatch { expected: u16, found: u16 },
+    #[error("walked {walked} of {requested} names")]
+    ShortWalk { requested: usize, walked: usize },
+    #[error("unsupported protocol version {0:?}")]
+    UnsupportedVersion(String),
+    #[error("protocol error: {0}")]
+    Protocol(String),
+}
+
+impl P9Error {
+    /// The error for an Rlerror carrying `ecode`.
+    pub fn from_ecode(ecode: u32) -> Self {
+        match Errno::from_linux(ecode) {
+            Some(errno) => P9Error::Server(errno),
+            None => P9Error::UnknownServerErrno(ecode),
+        }
+    }
+}
+
+impl From<P9Error> for io::Error {
+    fn from(e: P9Error) -> Self {
+        let kind = match e {
+            P9Error::Io(e) => return e,
+            P9Error::Server(errno) => return errno.into(),
+            P9Error::NotConnected => io::ErrorKind::NotConnected,
+            // A walk stops short at the first name that does not exist.
+            P9Error::ShortWalk { .. } => io::ErrorKind::NotFound,
+            P9Error::Lock(LockStatus::Blocked) => io::ErrorKind::WouldBlock,
+            P9Error::ShortMessage(_)
+            | P9Error::MessageTooLarge { .. }
+            | P9Error::UnknownMessageType(_)
+            | P9Error::Decode(_)
+            | P9Error::UnexpectedReturnType(..)
+            | P9Error::TagMismatch { .. }
+            | P9Error::Protocol(_) => io::ErrorKind::InvalidData,
+            P9Error::UnsupportedVersion(_) => io::ErrorKind::Unsupported,
+            P9Error::UnknownServerErrno(_)
+            | P9Error::ServerMessage(_)
+            | P9Error::Lock(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
 }
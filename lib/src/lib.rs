@@ -4,5 +4,11 @@
 
 // Copyright 2022 Oxide Computer Company
 
+// Lets the derive macros refer to p9ds paths from within p9ds itself.
+extern crate self as p9ds;
+
+pub mod auth;
+pub mod codec;
+pub mod errno;
 pub mod error;
 pub mod proto;
@@ -4,12 +4,18 @@
 
 // Copyright 2022 Oxide Computer Company
 
+use crate::errno::Errno;
+use crate::error::P9Error;
+use bitflags::bitflags;
 use ispf;
 use ispf::WireSize;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use p9ds_derive::{P9Message, WireSize};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::fmt::{self, Display, Formatter};
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::mem::size_of;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -20,7 +26,7 @@ pub enum OpenFlags {
     RdWr,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum P9Version {
     V2000,
     V2000U,
@@ -33,7 +39,7 @@ impl P9Version {
     pub fn to_string(self) -> String {
         match self {
             Self::V2000 => "9P2000".into(),
-            Self::V2000U => "9P2000.U".into(),
+            Self::V2000U => "9P2000.u".into(),
             Self::V2000L => "9P2000.L".into(),
             Self::V2000P4 => "9P2000.P4".into(),
         }
@@ -43,7 +49,7 @@ impl P9Version {
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "9P2000" => Some(Self::V2000),
-            "9P2000.U" => Some(Self::V2000U),
+            "9P2000.u" | "9P2000.U" => Some(Self::V2000U),
             "9P2000.L" => Some(Self::V2000L),
             "9P2000.P4" => Some(Self::V2000P4),
             _ => None,
@@ -61,6 +67,7 @@ impl P9Version {
     Deserialize_repr,
     TryFromPrimitive,
     IntoPrimitive,
+    WireSize,
 )]
 #[repr(u8)]
 pub enum MessageType {
@@ -83,8 +90,12 @@ pub enum MessageType {
     Rreadlink,
     Tgetattr = 24,
     Rgetattr,
+    Tsetattr = 26,
+    Rsetattr,
     Txattrwalk = 30,
     Rxattrwalk,
+    Txattrcreate = 32,
+    Rxattrcreate,
     Treaddir = 40,
     Rreaddir,
     Tfsync = 50,
@@ -107,10 +118,16 @@ pub enum MessageType {
     Rauth,
     Tattach = 104,
     Rattach,
+    Terror = 106,
+    Rerror,
     Tflush = 108,
     Rflush,
     Twalk = 110,
     Rwalk,
+    Topen = 112,
+    Ropen,
+    Tcreate = 114,
+    Rcreate,
     Tread = 116,
     Rread,
     Twrite = 118,
@@ -119,6 +136,10 @@ pub enum MessageType {
     Rclunk,
     Tremove = 122,
     Rremove,
+    Tstat = 124,
+    Rstat,
+    Twstat = 126,
+    Rwstat,
 }
 
 impl Display for MessageType {
@@ -132,44 +153,83 @@ pub trait Message {
     fn instance_type(&self) -> MessageType;
 }
 
-#[derive(
-    Debug,
-    PartialEq,
-    Eq,
-    Serialize_repr,
-    Deserialize_repr,
-    TryFromPrimitive,
-    IntoPrimitive,
-)]
-#[repr(u8)]
-pub enum QidType {
-    Dir = 0x80,
-    Append = 0x40,
-    Excl = 0x20,
-    Mount = 0x10,
-    Auth = 0x08,
-    Tmp = 0x04,
-    Link = 0x02,
-    File = 0x00,
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
-pub struct Partial {
-    pub size: u32,
-    pub typ: MessageType,
-    pub tag: u16,
+/// A T-message, which a server answers with its `Response` or an error.
+pub trait Request: Message + Serialize + Debug + Send + Sync {
+    type Response: Message
+        + DeserializeOwned
+        + TryFrom<Fcall, Error = Fcall>
+        + Debug
+        + Send;
+}
+
+bitflags! {
+    /// The type bits of a qid. Servers may combine these, so any byte is
+    /// accepted when decoding, including bits not named here.
+    #[derive(Default, Serialize, Deserialize, WireSize)]
+    #[serde(transparent)]
+    pub struct QidType: u8 {
+        const DIR = 0x80;
+        const APPEND = 0x40;
+        const EXCL = 0x20;
+        const MOUNT = 0x10;
+        const AUTH = 0x08;
+        const TMP = 0x04;
+        const SYMLINK = 0x02;
+        const LINK = 0x01;
+        const FILE = 0x00;
+    }
 }
 
-impl Message for Partial {
-    fn instance_type(&self) -> MessageType {
-        self.typ
+impl QidType {
+    pub fn is_dir(&self) -> bool {
+        self.contains(QidType::DIR)
     }
-    fn message_type() -> MessageType {
-        MessageType::Unknown
+
+    pub fn is_append(&self) -> bool {
+        self.contains(QidType::APPEND)
+    }
+
+    pub fn is_excl(&self) -> bool {
+        self.contains(QidType::EXCL)
+    }
+
+    pub fn is_mount(&self) -> bool {
+        self.contains(QidType::MOUNT)
+    }
+
+    pub fn is_auth(&self) -> bool {
+        self.contains(QidType::AUTH)
+    }
+
+    pub fn is_tmp(&self) -> bool {
+        self.contains(QidType::TMP)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.contains(QidType::SYMLINK)
+    }
+
+    pub fn is_link(&self) -> bool {
+        self.contains(QidType::LINK)
+    }
+
+    /// A plain file, possibly append only, exclusive use or temporary.
+    pub fn is_file(&self) -> bool {
+        !self.intersects(
+            QidType::DIR | QidType::MOUNT | QidType::AUTH | QidType::SYMLINK,
+        )
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Unknown")]
+pub struct Partial {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rlerror {
     pub size: u32,
     pub typ: MessageType,
@@ -180,20 +240,17 @@ pub struct Rlerror {
 impl Rlerror {
     pub fn new(ecode: u32) -> Self {
         Rlerror {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // ecode
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rlerror,
             tag: 0,
             ecode,
         }
+        .sized()
+    }
+
+    /// The error as a Linux errno, if it is one this crate knows about.
+    pub fn errno(&self) -> Option<Errno> {
+        Errno::from_linux(self.ecode)
     }
 }
 
@@ -203,21 +260,14 @@ impl Display for Rlerror {
     }
 }
 
-impl Message for Rlerror {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rlerror
-    }
-}
-
+pub const NO_TAG: u16 = !0u16;
 pub const NO_FID: u32 = !0u32;
 pub const NO_AFID: u32 = !0u32;
 pub const NO_NUNAME: u32 = !0u32;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
-pub struct Version {
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rversion")]
+pub struct Tversion {
     pub size: u32,
     pub typ: MessageType,
     pub tag: u16,
@@ -226,42 +276,47 @@ pub struct Version {
     pub version: String,
 }
 
-impl Message for Version {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rversion
-    }
-}
-
-impl Version {
+impl Tversion {
     pub fn new(v: P9Version) -> Self {
         let vs = v.to_string();
-        Version {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // msize
-                size_of::<u32>() +
-                // version.size
-                size_of::<u16>() +
-                // version
-                vs.len()
-            ) as u32,
+        Tversion {
+            size: 0,
             typ: MessageType::Tversion,
             tag: 0,
             msize: 0x8000, //32 kB default
             version: vs,
         }
+        .sized()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rversion {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub msize: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub version: String,
+}
+
+impl Rversion {
+    /// Answer a Tversion with the agreed `msize` and `version`. The version
+    /// is "unknown" to refuse the one the client asked for.
+    pub fn new(msize: u32, version: String) -> Self {
+        Rversion {
+            size: 0,
+            typ: MessageType::Rversion,
+            tag: 0,
+            msize,
+            version,
+        }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rclunk")]
 pub struct Tclunk {
     pub size: u32,
     pub typ: MessageType,
@@ -272,33 +327,16 @@ pub struct Tclunk {
 impl Tclunk {
     pub fn new(fid: u32) -> Self {
         Tclunk {
-            size: (
-                //size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tclunk,
             tag: 0,
             fid,
         }
+        .sized()
     }
 }
 
-impl Message for Tclunk {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Tclunk
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rclunk {
     pub size: u32,
     pub typ: MessageType,
@@ -308,26 +346,11 @@ pub struct Rclunk {
 impl Rclunk {
     pub fn new() -> Self {
         Rclunk {
-            size: (
-                //size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rclunk,
             tag: 0,
         }
-    }
-}
-
-impl Message for Rclunk {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rclunk
+        .sized()
     }
 }
 
@@ -340,7 +363,8 @@ impl Default for Rclunk {
 /*
 size[4] Tgetattr tag[2] fid[4] request_mask[8]
 */
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rgetattr")]
 pub struct Tgetattr {
     pub size: u32,
     pub typ: MessageType,
@@ -352,33 +376,13 @@ pub struct Tgetattr {
 impl Tgetattr {
     pub fn new(fid: u32, request_mask: u64) -> Self {
         Tgetattr {
-            size: (
-                //size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // mask
-                size_of::<u64>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tgetattr,
             tag: 0,
             fid,
             request_mask,
         }
-    }
-}
-
-impl Message for Tgetattr {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-
-    fn message_type() -> MessageType {
-        MessageType::Tgetattr
+        .sized()
     }
 }
 
@@ -425,7 +429,7 @@ size[4] Rgetattr
     gen[8]
     data_version[8]
 */
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rgetattr {
     pub size: u32,
     pub typ: MessageType,
@@ -477,58 +481,7 @@ impl Rgetattr {
         data_version: u64,
     ) -> Self {
         Rgetattr {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                //valid
-                size_of::<u64>() +
-                // qid.typ
-                size_of::<QidType>() +
-                // qid.version
-                size_of::<u32>() +
-                // qid.path
-                size_of::<u64>() +
-                //  mode
-                size_of::<u32>() +
-                //  uid
-                size_of::<u32>() +
-                //  gid
-                size_of::<u32>() +
-                //  nlink
-                size_of::<u64>() +
-                //  rdev
-                size_of::<u64>() +
-                //  attrsize
-                size_of::<u64>() +
-                //  blksize
-                size_of::<u64>() +
-                //  blocks
-                size_of::<u64>() +
-                //  atime_sec
-                size_of::<u64>() +
-                //  atime_nsec
-                size_of::<u64>() +
-                //  mtime_sec
-                size_of::<u64>() +
-                //  mtime_nsec
-                size_of::<u64>() +
-                //  ctime_sec
-                size_of::<u64>() +
-                //  ctime_nsec
-                size_of::<u64>() +
-                //  btime_sec
-                size_of::<u64>() +
-                //  btime_nsec
-                size_of::<u64>() +
-                //  gen
-                size_of::<u64>() +
-                //  data_version
-                size_of::<u64>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rgetattr,
             tag: 0,
             valid,
@@ -552,23 +505,236 @@ impl Rgetattr {
             gen,
             data_version,
         }
+        .sized()
+    }
+}
+
+bitflags! {
+    #[derive(Default, Serialize, Deserialize, WireSize)]
+    #[serde(transparent)]
+    pub struct SetAttrMask: u32 {
+        const MODE = 0x00000001;
+        const UID = 0x00000002;
+        const GID = 0x00000004;
+        const SIZE = 0x00000008;
+        const ATIME = 0x00000010;
+        const MTIME = 0x00000020;
+        const CTIME = 0x00000040;
+        const ATIME_SET = 0x00000080;
+        const MTIME_SET = 0x00000100;
+    }
+}
+
+/*
+size[4] Tsetattr
+    tag[2]
+    fid[4]
+    valid[4]
+    mode[4]
+    uid[4]
+    gid[4]
+    size[8]
+    atime_sec[8]
+    atime_nsec[8]
+    mtime_sec[8]
+    mtime_nsec[8]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rsetattr")]
+pub struct Tsetattr {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub valid: SetAttrMask,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub attrsize: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+}
+
+impl Tsetattr {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fid: u32,
+        valid: SetAttrMask,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        attrsize: u64,
+        atime_sec: u64,
+        atime_nsec: u64,
+        mtime_sec: u64,
+        mtime_nsec: u64,
+    ) -> Self {
+        Tsetattr {
+            size: 0,
+            typ: MessageType::Tsetattr,
+            tag: 0,
+            fid,
+            valid,
+            mode,
+            uid,
+            gid,
+            attrsize,
+            atime_sec,
+            atime_nsec,
+            mtime_sec,
+            mtime_nsec,
+        }
+        .sized()
     }
 }
 
-impl Message for Rgetattr {
-    fn instance_type(&self) -> MessageType {
-        self.typ
+/*
+size[4] Rsetattr tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rsetattr {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rsetattr {
+    pub fn new() -> Self {
+        Rsetattr {
+            size: 0,
+            typ: MessageType::Rsetattr,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rsetattr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+size[4] Txattrwalk tag[2] fid[4] newfid[4] name[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rxattrwalk")]
+pub struct Txattrwalk {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub newfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+}
+
+impl Txattrwalk {
+    pub fn new(fid: u32, newfid: u32, name: String) -> Self {
+        Txattrwalk {
+            size: 0,
+            typ: MessageType::Txattrwalk,
+            tag: 0,
+            fid,
+            newfid,
+            name,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rxattrwalk tag[2] size[8]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rxattrwalk {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub attrsize: u64,
+}
+
+impl Rxattrwalk {
+    pub fn new(attrsize: u64) -> Self {
+        Rxattrwalk {
+            size: 0,
+            typ: MessageType::Rxattrwalk,
+            tag: 0,
+            attrsize,
+        }
+        .sized()
+    }
+}
+
+pub const P9_XATTR_CREATE: u32 = 0x1;
+pub const P9_XATTR_REPLACE: u32 = 0x2;
+
+/*
+size[4] Txattrcreate tag[2] fid[4] name[s] attr_size[8] flags[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rxattrcreate")]
+pub struct Txattrcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub attrsize: u64,
+    pub flags: u32,
+}
+
+impl Txattrcreate {
+    pub fn new(fid: u32, name: String, attrsize: u64, flags: u32) -> Self {
+        Txattrcreate {
+            size: 0,
+            typ: MessageType::Txattrcreate,
+            tag: 0,
+            fid,
+            name,
+            attrsize,
+            flags,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rxattrcreate tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rxattrcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rxattrcreate {
+    pub fn new() -> Self {
+        Rxattrcreate {
+            size: 0,
+            typ: MessageType::Rxattrcreate,
+            tag: 0,
+        }
+        .sized()
     }
+}
 
-    fn message_type() -> MessageType {
-        MessageType::Rgetattr
+impl Default for Rxattrcreate {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 /*
 size[4] Tstatfs tag[2] fid[4]
 */
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rstatfs")]
 pub struct Tstatfs {
     pub size: u32,
     pub typ: MessageType,
@@ -579,20 +745,12 @@ pub struct Tstatfs {
 impl Tstatfs {
     pub fn new(fid: u32) -> Self {
         Tstatfs {
-            size: (
-                //size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tstatfs,
             tag: 0,
             fid,
         }
+        .sized()
     }
 }
 
@@ -609,7 +767,7 @@ size[4] Rstatfs
     fsid[8]
     namelen[4]
 */
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rstatfs {
     pub size: u32,
     pub typ: MessageType,
@@ -639,32 +797,7 @@ impl Rstatfs {
         namelen: u32,
     ) -> Self {
         Rstatfs {
-            size: (
-                //size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // fstype
-                size_of::<u32>() +
-                // bsize
-                size_of::<u32>() +
-                // blocks
-                size_of::<u64>() +
-                // bfree
-                size_of::<u64>() +
-                // bavail
-                size_of::<u64>() +
-                // files
-                size_of::<u64>() +
-                // ffree
-                size_of::<u64>() +
-                // fsid
-                size_of::<u64>() +
-                // namelen
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rstatfs,
             tag: 0,
             fstype,
@@ -677,10 +810,74 @@ impl Rstatfs {
             fsid,
             namelen,
         }
+        .sized()
+    }
+}
+
+/*
+size[4] Tauth tag[2] afid[4] uname[s] aname[s] n_uname[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rauth")]
+pub struct Tauth {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub afid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub uname: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub aname: String,
+    pub n_uname: u32,
+}
+
+impl Tauth {
+    pub fn new(afid: u32, uname: String, aname: String, n_uname: u32) -> Self {
+        Tauth {
+            size: 0,
+            typ: MessageType::Tauth,
+            tag: 0,
+            afid,
+            uname,
+            aname,
+            n_uname,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rauth tag[2] aqid[13]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rauth {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub aqid: Qid,
+}
+
+impl Rauth {
+    pub fn new(aqid: Qid) -> Self {
+        Rauth {
+            size: 0,
+            typ: MessageType::Rauth,
+            tag: 0,
+            aqid,
+        }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/*
+size[4] Tattach tag[2] fid[4] afid[4] uname[s] aname[s] n_uname[4]
+
+The n_uname field was introduced by 9P2000.u and kept by 9P2000.L. It carries
+the numeric uid of the attaching user, which servers use in preference to
+uname unless it is NO_NUNAME.
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rattach")]
 pub struct Tattach {
     pub size: u32,
     pub typ: MessageType,
@@ -703,28 +900,7 @@ impl Tattach {
         n_uname: u32,
     ) -> Self {
         Tattach {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>() +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // afid
-                size_of::<u32>() +
-                // uname.size
-                size_of::<u16>() +
-                // uname
-                uname.len() +
-                // aname.size
-                size_of::<u16>() +
-                // aname
-                aname.len() +
-                // nuname
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tattach,
             tag: 0,
             fid,
@@ -733,10 +909,11 @@ impl Tattach {
             aname,
             n_uname,
         }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rattach {
     pub size: u32,
     pub typ: MessageType,
@@ -747,50 +924,84 @@ pub struct Rattach {
 impl Rattach {
     pub fn new(qid: Qid) -> Self {
         Rattach {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // qid.typ
-                size_of::<QidType>() +
-                // qid.version
-                size_of::<u32>() +
-                // qid.path
-                size_of::<u64>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rattach,
             tag: 0,
             qid,
         }
+        .sized()
     }
 }
 
-impl Message for Rattach {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rattach
-    }
+/*
+size[4] Tflush tag[2] oldtag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rflush")]
+pub struct Tflush {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub oldtag: u16,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+impl Tflush {
+    pub fn new(oldtag: u16) -> Self {
+        Tflush {
+            size: 0,
+            typ: MessageType::Tflush,
+            tag: 0,
+            oldtag,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rflush tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rflush {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rflush {
+    pub fn new() -> Self {
+        Rflush {
+            size: 0,
+            typ: MessageType::Rflush,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rflush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, WireSize)]
 pub struct Qid {
     pub typ: QidType,
     pub version: u32,
     pub path: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, WireSize)]
 pub struct Wname {
     #[serde(with = "ispf::str_lv16")]
     pub value: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// The most names a single Twalk may carry.
+pub const P9_MAXWELEM: usize = 16;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rwalk")]
 pub struct Twalk {
     pub size: u32,
     pub typ: MessageType,
@@ -803,37 +1014,19 @@ pub struct Twalk {
 
 impl Twalk {
     pub fn new(fid: u32, newfid: u32, wname: Vec<Wname>) -> Self {
-        let mut wname_sz = 0usize;
-        for x in &wname {
-            // leading length u16 plus string
-            wname_sz += size_of::<u16>() + x.value.len()
-        }
         Twalk {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // newfid
-                size_of::<u32>() +
-                // wname.len
-                size_of::<u16>() +
-                wname_sz
-            ) as u32,
+            size: 0,
             typ: MessageType::Twalk,
             tag: 0,
             fid,
             newfid,
             wname,
         }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rwalk {
     pub size: u32,
     pub typ: MessageType,
@@ -844,37 +1037,33 @@ pub struct Rwalk {
 
 impl Rwalk {
     pub fn new(wname: Vec<Qid>) -> Self {
-        let wname_sz = wname.len()
-            * (size_of::<QidType>() + size_of::<u32>() + size_of::<u64>());
         Rwalk {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // wname.len
-                size_of::<u16>() +
-                wname_sz
-            ) as u32,
+            size: 0,
             typ: MessageType::Rwalk,
             tag: 0,
             wname,
         }
+        .sized()
     }
 }
 
-impl Message for Rwalk {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rwalk
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+// Linux open flags as 9P2000.L carries them in Tlopen and Tlcreate. These
+// are the Linux values whatever the host's own are. The access mode is one
+// of the OpenFlags.
+pub const P9_DOTL_CREATE: u32 = 0o100;
+pub const P9_DOTL_EXCL: u32 = 0o200;
+pub const P9_DOTL_NOCTTY: u32 = 0o400;
+pub const P9_DOTL_TRUNC: u32 = 0o1000;
+pub const P9_DOTL_APPEND: u32 = 0o2000;
+pub const P9_DOTL_NONBLOCK: u32 = 0o4000;
+pub const P9_DOTL_DSYNC: u32 = 0o10000;
+pub const P9_DOTL_DIRECTORY: u32 = 0o200000;
+pub const P9_DOTL_NOFOLLOW: u32 = 0o400000;
+pub const P9_DOTL_CLOEXEC: u32 = 0o2000000;
+pub const P9_DOTL_SYNC: u32 = 0o4000000;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rlopen")]
 pub struct Tlopen {
     pub size: u32,
     pub typ: MessageType,
@@ -886,27 +1075,17 @@ pub struct Tlopen {
 impl Tlopen {
     pub fn new(fid: u32, flags: u32) -> Self {
         Tlopen {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // flags
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tlopen,
             tag: 0,
             fid,
             flags,
         }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rlopen {
     pub size: u32,
     pub typ: MessageType,
@@ -918,40 +1097,18 @@ pub struct Rlopen {
 impl Rlopen {
     pub fn new(qid: Qid, iounit: u32) -> Self {
         Rlopen {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // qid.typ
-                size_of::<QidType>() +
-                // qid.version
-                size_of::<u32>() +
-                // qid.path
-                size_of::<u64>() +
-                // iounit
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rlopen,
             tag: 0,
             qid,
             iounit,
         }
+        .sized()
     }
 }
 
-impl Message for Rlopen {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rlopen
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rreaddir")]
 pub struct Treaddir {
     pub size: u32,
     pub typ: MessageType,
@@ -964,30 +1121,18 @@ pub struct Treaddir {
 impl Treaddir {
     pub fn new(fid: u32, offset: u64, count: u32) -> Self {
         Treaddir {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // offset
-                size_of::<u64>() +
-                // count
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Treaddir,
             tag: 0,
             fid,
             offset,
             count,
         }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rreaddir {
     pub size: u32,
     pub typ: MessageType,
@@ -998,41 +1143,28 @@ pub struct Rreaddir {
 
 impl Rreaddir {
     pub fn new(data: Vec<Dirent>) -> Self {
-        let mut data_sz = 0usize;
-        for x in &data {
-            // leading length u16 plus string
-            data_sz += x.wire_size();
-        }
-
         Rreaddir {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // data.len
-                size_of::<u32>() +
-                data_sz
-            ) as u32,
+            size: 0,
             typ: MessageType::Rreaddir,
             tag: 0,
             data,
         }
+        .sized()
     }
 }
 
-impl Message for Rreaddir {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rreaddir
-    }
-}
+// Dirent types, the Linux d_type values, which 9P2000.L servers send
+// whatever the host's own values are.
+pub const P9_DT_UNKNOWN: u8 = 0;
+pub const P9_DT_FIFO: u8 = 1;
+pub const P9_DT_CHR: u8 = 2;
+pub const P9_DT_DIR: u8 = 4;
+pub const P9_DT_BLK: u8 = 6;
+pub const P9_DT_REG: u8 = 8;
+pub const P9_DT_LNK: u8 = 10;
+pub const P9_DT_SOCK: u8 = 12;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, WireSize)]
 pub struct Dirent {
     pub qid: Qid,
     pub offset: u64,
@@ -1041,27 +1173,8 @@ pub struct Dirent {
     pub name: String,
 }
 
-impl ispf::WireSize for Dirent {
-    fn wire_size(&self) -> usize {
-        // qid.typ
-        size_of::<QidType>() +
-        // qid.version
-        size_of::<u32>() +
-        // qid.path
-        size_of::<u64>() +
-        // offset
-        size_of::<u64>() +
-        // typ
-        size_of::<u8>() +
-        // name.len TODO: awkward, user specifying
-        //                serde inserted value
-        size_of::<u16>() +
-        // name
-        self.name.len()
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rread")]
 pub struct Tread {
     pub size: u32,
     pub typ: MessageType,
@@ -1074,30 +1187,18 @@ pub struct Tread {
 impl Tread {
     pub fn new(fid: u32, offset: u64, count: u32) -> Self {
         Tread {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // offset
-                size_of::<u64>() +
-                // count
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Tread,
             tag: 0,
             fid,
             offset,
             count,
         }
+        .sized()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rread {
     pub size: u32,
     pub typ: MessageType,
@@ -1109,34 +1210,17 @@ pub struct Rread {
 impl Rread {
     pub fn new(data: Vec<u8>) -> Self {
         Rread {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // data.count
-                size_of::<u32>() +
-                data.len()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rread,
             tag: 0,
             data,
         }
+        .sized()
     }
 }
 
-impl Message for Rread {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Rread
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rwrite")]
 pub struct Twrite {
     pub size: u32,
     pub typ: MessageType,
@@ -1150,40 +1234,18 @@ pub struct Twrite {
 impl Twrite {
     pub fn new(data: Vec<u8>, fid: u32, offset: u64) -> Self {
         Twrite {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>() +
-                // offset
-                size_of::<u64>() +
-                // data.count
-                size_of::<u32>() +
-                data.len()
-            ) as u32,
+            size: 0,
             typ: MessageType::Twrite,
             tag: 0,
             fid,
             offset,
             data,
         }
+        .sized()
     }
 }
 
-impl Message for Twrite {
-    fn instance_type(&self) -> MessageType {
-        self.typ
-    }
-    fn message_type() -> MessageType {
-        MessageType::Twrite
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
 pub struct Rwrite {
     pub size: u32,
     pub typ: MessageType,
@@ -1194,28 +1256,1748 @@ pub struct Rwrite {
 impl Rwrite {
     pub fn new(count: u32) -> Self {
         Rwrite {
-            size: (
-                // size
-                size_of::<u32>() +
-                // typ
-                size_of::<u8>()  +
-                // tag
-                size_of::<u16>() +
-                // fid
-                size_of::<u32>()
-            ) as u32,
+            size: 0,
             typ: MessageType::Rwrite,
             tag: 0,
             count,
         }
+        .sized()
+    }
+}
+
+/*
+size[4] Tlcreate tag[2] fid[4] name[s] flags[4] mode[4] gid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rlcreate")]
+pub struct Tlcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub flags: u32,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+impl Tlcreate {
+    pub fn new(
+        fid: u32,
+        name: String,
+        flags: u32,
+        mode: u32,
+        gid: u32,
+    ) -> Self {
+        Tlcreate {
+            size: 0,
+            typ: MessageType::Tlcreate,
+            tag: 0,
+            fid,
+            name,
+            flags,
+            mode,
+            gid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rlcreate tag[2] qid[13] iounit[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rlcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+impl Rlcreate {
+    pub fn new(qid: Qid, iounit: u32) -> Self {
+        Rlcreate {
+            size: 0,
+            typ: MessageType::Rlcreate,
+            tag: 0,
+            qid,
+            iounit,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tsymlink tag[2] fid[4] name[s] symtgt[s] gid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rsymlink")]
+pub struct Tsymlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub symtgt: String,
+    pub gid: u32,
+}
+
+impl Tsymlink {
+    pub fn new(fid: u32, name: String, symtgt: String, gid: u32) -> Self {
+        Tsymlink {
+            size: 0,
+            typ: MessageType::Tsymlink,
+            tag: 0,
+            fid,
+            name,
+            symtgt,
+            gid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rsymlink tag[2] qid[13]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rsymlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+}
+
+impl Rsymlink {
+    pub fn new(qid: Qid) -> Self {
+        Rsymlink {
+            size: 0,
+            typ: MessageType::Rsymlink,
+            tag: 0,
+            qid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tmknod tag[2] dfid[4] name[s] mode[4] major[4] minor[4] gid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rmknod")]
+pub struct Tmknod {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub dfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub mode: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub gid: u32,
+}
+
+impl Tmknod {
+    pub fn new(
+        dfid: u32,
+        name: String,
+        mode: u32,
+        major: u32,
+        minor: u32,
+        gid: u32,
+    ) -> Self {
+        Tmknod {
+            size: 0,
+            typ: MessageType::Tmknod,
+            tag: 0,
+            dfid,
+            name,
+            mode,
+            major,
+            minor,
+            gid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rmknod tag[2] qid[13]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rmknod {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+}
+
+impl Rmknod {
+    pub fn new(qid: Qid) -> Self {
+        Rmknod {
+            size: 0,
+            typ: MessageType::Rmknod,
+            tag: 0,
+            qid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Trename tag[2] fid[4] dfid[4] name[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rrename")]
+pub struct Trename {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub dfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+}
+
+impl Trename {
+    pub fn new(fid: u32, dfid: u32, name: String) -> Self {
+        Trename {
+            size: 0,
+            typ: MessageType::Trename,
+            tag: 0,
+            fid,
+            dfid,
+            name,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rrename tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rrename {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rrename {
+    pub fn new() -> Self {
+        Rrename {
+            size: 0,
+            typ: MessageType::Rrename,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rrename {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+size[4] Treadlink tag[2] fid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rreadlink")]
+pub struct Treadlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+}
+
+impl Treadlink {
+    pub fn new(fid: u32) -> Self {
+        Treadlink {
+            size: 0,
+            typ: MessageType::Treadlink,
+            tag: 0,
+            fid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rreadlink tag[2] target[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rreadlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    #[serde(with = "ispf::str_lv16")]
+    pub target: String,
+}
+
+impl Rreadlink {
+    pub fn new(target: String) -> Self {
+        Rreadlink {
+            size: 0,
+            typ: MessageType::Rreadlink,
+            tag: 0,
+            target,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tfsync tag[2] fid[4] datasync[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rfsync")]
+pub struct Tfsync {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub datasync: u32,
+}
+
+impl Tfsync {
+    pub fn new(fid: u32, datasync: u32) -> Self {
+        Tfsync {
+            size: 0,
+            typ: MessageType::Tfsync,
+            tag: 0,
+            fid,
+            datasync,
+        }
+        .sized()
     }
 }
 
-impl Message for Rwrite {
-    fn instance_type(&self) -> MessageType {
-        self.typ
+/*
+size[4] Rfsync tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rfsync {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rfsync {
+    pub fn new() -> Self {
+        Rfsync {
+            size: 0,
+            typ: MessageType::Rfsync,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rfsync {
+    fn default() -> Self {
+        Self::new()
     }
-    fn message_type() -> MessageType {
-        MessageType::Rwrite
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Serialize_repr,
+    Deserialize_repr,
+    TryFromPrimitive,
+    IntoPrimitive,
+    WireSize,
+)]
+#[repr(u8)]
+pub enum LockType {
+    Rdlck = 0,
+    Wrlck = 1,
+    Unlck = 2,
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Serialize_repr,
+    Deserialize_repr,
+    TryFromPrimitive,
+    IntoPrimitive,
+    WireSize,
+)]
+#[repr(u8)]
+pub enum LockStatus {
+    Success = 0,
+    Blocked = 1,
+    Error = 2,
+    Grace = 3,
+}
+
+pub const P9_LOCK_FLAGS_BLOCK: u32 = 0x1;
+pub const P9_LOCK_FLAGS_RECLAIM: u32 = 0x2;
+
+/*
+size[4] Tlock
+    tag[2]
+    fid[4]
+    type[1]
+    flags[4]
+    start[8]
+    length[8]
+    proc_id[4]
+    client_id[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rlock")]
+pub struct Tlock {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub lock_type: LockType,
+    pub flags: u32,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub client_id: String,
+}
+
+impl Tlock {
+    pub fn new(
+        fid: u32,
+        lock_type: LockType,
+        flags: u32,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    ) -> Self {
+        Tlock {
+            size: 0,
+            typ: MessageType::Tlock,
+            tag: 0,
+            fid,
+            lock_type,
+            flags,
+            start,
+            length,
+            proc_id,
+            client_id,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rlock tag[2] status[1]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rlock {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub status: LockStatus,
+}
+
+impl Rlock {
+    pub fn new(status: LockStatus) -> Self {
+        Rlock {
+            size: 0,
+            typ: MessageType::Rlock,
+            tag: 0,
+            status,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tgetlock
+    tag[2]
+    fid[4]
+    type[1]
+    start[8]
+    length[8]
+    proc_id[4]
+    client_id[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rgetlock")]
+pub struct Tgetlock {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub lock_type: LockType,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub client_id: String,
+}
+
+impl Tgetlock {
+    pub fn new(
+        fid: u32,
+        lock_type: LockType,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    ) -> Self {
+        Tgetlock {
+            size: 0,
+            typ: MessageType::Tgetlock,
+            tag: 0,
+            fid,
+            lock_type,
+            start,
+            length,
+            proc_id,
+            client_id,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rgetlock
+    tag[2]
+    type[1]
+    start[8]
+    length[8]
+    proc_id[4]
+    client_id[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rgetlock {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub lock_type: LockType,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub client_id: String,
+}
+
+impl Rgetlock {
+    pub fn new(
+        lock_type: LockType,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    ) -> Self {
+        Rgetlock {
+            size: 0,
+            typ: MessageType::Rgetlock,
+            tag: 0,
+            lock_type,
+            start,
+            length,
+            proc_id,
+            client_id,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tlink tag[2] dfid[4] fid[4] name[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rlink")]
+pub struct Tlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub dfid: u32,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+}
+
+impl Tlink {
+    pub fn new(dfid: u32, fid: u32, name: String) -> Self {
+        Tlink {
+            size: 0,
+            typ: MessageType::Tlink,
+            tag: 0,
+            dfid,
+            fid,
+            name,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rlink tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rlink {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rlink {
+    pub fn new() -> Self {
+        Rlink {
+            size: 0,
+            typ: MessageType::Rlink,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rlink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+size[4] Tmkdir tag[2] dfid[4] name[s] mode[4] gid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rmkdir")]
+pub struct Tmkdir {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub dfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+impl Tmkdir {
+    pub fn new(dfid: u32, name: String, mode: u32, gid: u32) -> Self {
+        Tmkdir {
+            size: 0,
+            typ: MessageType::Tmkdir,
+            tag: 0,
+            dfid,
+            name,
+            mode,
+            gid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rmkdir tag[2] qid[13]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rmkdir {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+}
+
+impl Rmkdir {
+    pub fn new(qid: Qid) -> Self {
+        Rmkdir {
+            size: 0,
+            typ: MessageType::Rmkdir,
+            tag: 0,
+            qid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Trenameat
+    tag[2]
+    olddirfid[4]
+    oldname[s]
+    newdirfid[4]
+    newname[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rrenameat")]
+pub struct Trenameat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub olddirfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub oldname: String,
+    pub newdirfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub newname: String,
+}
+
+impl Trenameat {
+    pub fn new(
+        olddirfid: u32,
+        oldname: String,
+        newdirfid: u32,
+        newname: String,
+    ) -> Self {
+        Trenameat {
+            size: 0,
+            typ: MessageType::Trenameat,
+            tag: 0,
+            olddirfid,
+            oldname,
+            newdirfid,
+            newname,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rrenameat tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rrenameat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rrenameat {
+    pub fn new() -> Self {
+        Rrenameat {
+            size: 0,
+            typ: MessageType::Rrenameat,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rrenameat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const P9_AT_REMOVEDIR: u32 = 0x200;
+
+/*
+size[4] Tunlinkat tag[2] dirfid[4] name[s] flags[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Runlinkat")]
+pub struct Tunlinkat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub dirfid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub flags: u32,
+}
+
+impl Tunlinkat {
+    pub fn new(dirfid: u32, name: String, flags: u32) -> Self {
+        Tunlinkat {
+            size: 0,
+            typ: MessageType::Tunlinkat,
+            tag: 0,
+            dirfid,
+            name,
+            flags,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Runlinkat tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Runlinkat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Runlinkat {
+    pub fn new() -> Self {
+        Runlinkat {
+            size: 0,
+            typ: MessageType::Runlinkat,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Runlinkat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+size[4] Tremove tag[2] fid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rremove")]
+pub struct Tremove {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+}
+
+impl Tremove {
+    pub fn new(fid: u32) -> Self {
+        Tremove {
+            size: 0,
+            typ: MessageType::Tremove,
+            tag: 0,
+            fid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rremove tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rremove {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rremove {
+    pub fn new() -> Self {
+        Rremove {
+            size: 0,
+            typ: MessageType::Rremove,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rremove {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 9P2000 ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//
+// Messages of the classic Plan 9 dialect that 9P2000.L replaced with its own
+// variants.
+
+pub const P9_OREAD: u8 = 0x00;
+pub const P9_OWRITE: u8 = 0x01;
+pub const P9_ORDWR: u8 = 0x02;
+pub const P9_OEXEC: u8 = 0x03;
+pub const P9_OTRUNC: u8 = 0x10;
+pub const P9_ORCLOSE: u8 = 0x40;
+
+pub const P9_DMDIR: u32 = 0x80000000;
+pub const P9_DMAPPEND: u32 = 0x40000000;
+pub const P9_DMEXCL: u32 = 0x20000000;
+pub const P9_DMMOUNT: u32 = 0x10000000;
+pub const P9_DMAUTH: u32 = 0x08000000;
+pub const P9_DMTMP: u32 = 0x04000000;
+
+// 9P2000.u mode bits
+pub const P9_DMSYMLINK: u32 = 0x02000000;
+pub const P9_DMDEVICE: u32 = 0x00800000;
+pub const P9_DMNAMEDPIPE: u32 = 0x00200000;
+pub const P9_DMSOCKET: u32 = 0x00100000;
+pub const P9_DMSETUID: u32 = 0x00080000;
+pub const P9_DMSETGID: u32 = 0x00040000;
+
+/*
+size[4] Tauth tag[2] afid[4] uname[s] aname[s]
+
+Tauth as it was before 9P2000.u added n_uname.
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Tauth", response = "Rauth")]
+pub struct TauthClassic {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub afid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub uname: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub aname: String,
+}
+
+impl TauthClassic {
+    pub fn new(afid: u32, uname: String, aname: String) -> Self {
+        TauthClassic {
+            size: 0,
+            typ: MessageType::Tauth,
+            tag: 0,
+            afid,
+            uname,
+            aname,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tattach tag[2] fid[4] afid[4] uname[s] aname[s]
+
+Tattach as it was before 9P2000.u added n_uname.
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Tattach", response = "Rattach")]
+pub struct TattachClassic {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub afid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub uname: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub aname: String,
+}
+
+impl TattachClassic {
+    pub fn new(fid: u32, afid: u32, uname: String, aname: String) -> Self {
+        TattachClassic {
+            size: 0,
+            typ: MessageType::Tattach,
+            tag: 0,
+            fid,
+            afid,
+            uname,
+            aname,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rerror tag[2] ename[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rerror {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    #[serde(with = "ispf::str_lv16")]
+    pub ename: String,
+}
+
+impl Rerror {
+    pub fn new(ename: String) -> Self {
+        Rerror {
+            size: 0,
+            typ: MessageType::Rerror,
+            tag: 0,
+            ename,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Topen tag[2] fid[4] mode[1]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Ropen")]
+pub struct Topen {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub mode: u8,
+}
+
+impl Topen {
+    pub fn new(fid: u32, mode: u8) -> Self {
+        Topen {
+            size: 0,
+            typ: MessageType::Topen,
+            tag: 0,
+            fid,
+            mode,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Ropen tag[2] qid[13] iounit[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Ropen {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+impl Ropen {
+    pub fn new(qid: Qid, iounit: u32) -> Self {
+        Ropen {
+            size: 0,
+            typ: MessageType::Ropen,
+            tag: 0,
+            qid,
+            iounit,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tcreate tag[2] fid[4] name[s] perm[4] mode[1]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rcreate")]
+pub struct Tcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub perm: u32,
+    pub mode: u8,
+}
+
+impl Tcreate {
+    pub fn new(fid: u32, name: String, perm: u32, mode: u8) -> Self {
+        Tcreate {
+            size: 0,
+            typ: MessageType::Tcreate,
+            tag: 0,
+            fid,
+            name,
+            perm,
+            mode,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rcreate tag[2] qid[13] iounit[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rcreate {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+impl Rcreate {
+    pub fn new(qid: Qid, iounit: u32) -> Self {
+        Rcreate {
+            size: 0,
+            typ: MessageType::Rcreate,
+            tag: 0,
+            qid,
+            iounit,
+        }
+        .sized()
+    }
+}
+
+/*
+size[2]
+type[2]
+dev[4]
+qid[13]
+mode[4]
+atime[4]
+mtime[4]
+length[8]
+name[s]
+uid[s]
+gid[s]
+muid[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, WireSize)]
+pub struct Stat {
+    pub size: u16,
+    pub typ: u16,
+    pub dev: u32,
+    pub qid: Qid,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub uid: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub gid: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub muid: String,
+}
+
+/// Directory entries of the classic dialect are stat records, read from a
+/// directory fid with Tread.
+pub type Dir = Stat;
+
+impl Stat {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        typ: u16,
+        dev: u32,
+        qid: Qid,
+        mode: u32,
+        atime: u32,
+        mtime: u32,
+        length: u64,
+        name: String,
+        uid: String,
+        gid: String,
+        muid: String,
+    ) -> Self {
+        let mut stat = Stat {
+            size: 0,
+            typ,
+            dev,
+            qid,
+            mode,
+            atime,
+            mtime,
+            length,
+            name,
+            uid,
+            gid,
+            muid,
+        };
+        // The size field does not count itself.
+        stat.size = (stat.wire_size() - size_of::<u16>()) as u16;
+        stat
+    }
+
+    /// Decode the stat records returned by a Tread on a directory.
+    pub fn decode_dir(mut data: &[u8]) -> Result<Vec<Stat>, ispf::Error> {
+        let mut result = Vec::new();
+        while data.len() >= size_of::<u16>() {
+            let stat: Stat = ispf::from_bytes_le(data)?;
+            let n = stat.size as usize + size_of::<u16>();
+            if n > data.len() {
+                break;
+            }
+            data = &data[n..];
+            result.push(stat);
+        }
+        Ok(result)
+    }
+}
+
+/*
+size[4] Tstat tag[2] fid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rstat")]
+pub struct Tstat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+}
+
+impl Tstat {
+    pub fn new(fid: u32) -> Self {
+        Tstat {
+            size: 0,
+            typ: MessageType::Tstat,
+            tag: 0,
+            fid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rstat tag[2] stat[n]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rstat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub nstat: u16,
+    pub stat: Stat,
+}
+
+impl Rstat {
+    pub fn new(stat: Stat) -> Self {
+        let stat_sz = stat.wire_size();
+        Rstat {
+            size: 0,
+            typ: MessageType::Rstat,
+            tag: 0,
+            nstat: stat_sz as u16,
+            stat,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Twstat tag[2] fid[4] stat[n]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(response = "Rwstat")]
+pub struct Twstat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub nstat: u16,
+    pub stat: Stat,
+}
+
+impl Twstat {
+    pub fn new(fid: u32, stat: Stat) -> Self {
+        let stat_sz = stat.wire_size();
+        Twstat {
+            size: 0,
+            typ: MessageType::Twstat,
+            tag: 0,
+            fid,
+            nstat: stat_sz as u16,
+            stat,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rwstat tag[2]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+pub struct Rwstat {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+}
+
+impl Rwstat {
+    pub fn new() -> Self {
+        Rwstat {
+            size: 0,
+            typ: MessageType::Rwstat,
+            tag: 0,
+        }
+        .sized()
+    }
+}
+
+impl Default for Rwstat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 9P2000.u ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//
+// The Unix extensions to 9P2000 reuse the classic message types but append
+// fields to some of them, so which struct applies depends on the negotiated
+// version.
+
+/*
+size[4] Rerror tag[2] ename[s] errno[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Rerror")]
+pub struct RerrorU {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    #[serde(with = "ispf::str_lv16")]
+    pub ename: String,
+    pub errno: u32,
+}
+
+impl RerrorU {
+    pub fn new(ename: String, errno: u32) -> Self {
+        RerrorU {
+            size: 0,
+            typ: MessageType::Rerror,
+            tag: 0,
+            ename,
+            errno,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Tcreate tag[2] fid[4] name[s] perm[4] mode[1] extension[s]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Tcreate", response = "Rcreate")]
+pub struct TcreateU {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    pub perm: u32,
+    pub mode: u8,
+    #[serde(with = "ispf::str_lv16")]
+    pub extension: String,
+}
+
+impl TcreateU {
+    pub fn new(
+        fid: u32,
+        name: String,
+        perm: u32,
+        mode: u8,
+        extension: String,
+    ) -> Self {
+        TcreateU {
+            size: 0,
+            typ: MessageType::Tcreate,
+            tag: 0,
+            fid,
+            name,
+            perm,
+            mode,
+            extension,
+        }
+        .sized()
+    }
+}
+
+/*
+size[2]
+type[2]
+dev[4]
+qid[13]
+mode[4]
+atime[4]
+mtime[4]
+length[8]
+name[s]
+uid[s]
+gid[s]
+muid[s]
+extension[s]
+n_uid[4]
+n_gid[4]
+n_muid[4]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, WireSize)]
+pub struct StatU {
+    pub size: u16,
+    pub typ: u16,
+    pub dev: u32,
+    pub qid: Qid,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    #[serde(with = "ispf::str_lv16")]
+    pub name: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub uid: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub gid: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub muid: String,
+    #[serde(with = "ispf::str_lv16")]
+    pub extension: String,
+    pub n_uid: u32,
+    pub n_gid: u32,
+    pub n_muid: u32,
+}
+
+impl StatU {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        typ: u16,
+        dev: u32,
+        qid: Qid,
+        mode: u32,
+        atime: u32,
+        mtime: u32,
+        length: u64,
+        name: String,
+        uid: String,
+        gid: String,
+        muid: String,
+        extension: String,
+        n_uid: u32,
+        n_gid: u32,
+        n_muid: u32,
+    ) -> Self {
+        let mut stat = StatU {
+            size: 0,
+            typ,
+            dev,
+            qid,
+            mode,
+            atime,
+            mtime,
+            length,
+            name,
+            uid,
+            gid,
+            muid,
+            extension,
+            n_uid,
+            n_gid,
+            n_muid,
+        };
+        // The size field does not count itself.
+        stat.size = (stat.wire_size() - size_of::<u16>()) as u16;
+        stat
+    }
+
+    /// Decode the stat records returned by a Tread on a directory.
+    pub fn decode_dir(mut data: &[u8]) -> Result<Vec<StatU>, ispf::Error> {
+        let mut result = Vec::new();
+        while data.len() >= size_of::<u16>() {
+            let stat: StatU = ispf::from_bytes_le(data)?;
+            let n = stat.size as usize + size_of::<u16>();
+            if n > data.len() {
+                break;
+            }
+            data = &data[n..];
+            result.push(stat);
+        }
+        Ok(result)
+    }
+}
+
+/*
+size[4] Tstat tag[2] fid[4]
+
+The same message as the classic Tstat, but answered with an RstatU.
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Tstat", response = "RstatU")]
+pub struct TstatU {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+}
+
+impl TstatU {
+    pub fn new(fid: u32) -> Self {
+        TstatU {
+            size: 0,
+            typ: MessageType::Tstat,
+            tag: 0,
+            fid,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Rstat tag[2] stat[n]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Rstat")]
+pub struct RstatU {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub nstat: u16,
+    pub stat: StatU,
+}
+
+impl RstatU {
+    pub fn new(stat: StatU) -> Self {
+        let stat_sz = stat.wire_size();
+        RstatU {
+            size: 0,
+            typ: MessageType::Rstat,
+            tag: 0,
+            nstat: stat_sz as u16,
+            stat,
+        }
+        .sized()
+    }
+}
+
+/*
+size[4] Twstat tag[2] fid[4] stat[n]
+*/
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, P9Message)]
+#[p9(message_type = "Twstat", response = "Rwstat")]
+pub struct TwstatU {
+    pub size: u32,
+    pub typ: MessageType,
+    pub tag: u16,
+    pub fid: u32,
+    pub nstat: u16,
+    pub stat: StatU,
+}
+
+impl TwstatU {
+    pub fn new(fid: u32, stat: StatU) -> Self {
+        let stat_sz = stat.wire_size();
+        TwstatU {
+            size: 0,
+            typ: MessageType::Twstat,
+            tag: 0,
+            fid,
+            nstat: stat_sz as u16,
+            stat,
+        }
+        .sized()
+    }
+}
+
+// Fcall ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+macro_rules! fcall {
+    (
+        base { $($variant:ident($ty:ty),)* }
+        extended { $($xvariant:ident($xty:ty) => $xtyp:ident,)* }
+    ) => {
+        /// Any 9P message. Variants are named after the message type, except
+        /// for the 9P2000.u variants of messages that carry a `U` suffix and
+        /// the classic forms of messages 9P2000.u extended for everyone, which
+        /// carry a `Classic` suffix.
+        #[derive(Debug, PartialEq, Eq)]
+        pub enum Fcall {
+            $($variant($ty),)*
+            $($xvariant($xty),)*
+        }
+
+        impl Fcall {
+            fn decode_base(
+                typ: MessageType,
+                data: &[u8],
+            ) -> Result<Self, P9Error> {
+                match typ {
+                    $(MessageType::$variant => {
+                        Ok(Fcall::$variant(ispf::from_bytes_le(data)?))
+                    })*
+                    t => Err(P9Error::UnknownMessageType(t.into())),
+                }
+            }
+
+            /// Encode the message for the wire.
+            pub fn encode(&self) -> Result<Vec<u8>, P9Error> {
+                match self {
+                    $(Fcall::$variant(m) => Ok(ispf::to_bytes_le(m)?),)*
+                    $(Fcall::$xvariant(m) => Ok(ispf::to_bytes_le(m)?),)*
+                }
+            }
+
+            pub fn message_type(&self) -> MessageType {
+                match self {
+                    $(Fcall::$variant(_) => MessageType::$variant,)*
+                    $(Fcall::$xvariant(_) => MessageType::$xtyp,)*
+                }
+            }
+
+            pub fn tag(&self) -> u16 {
+                match self {
+                    $(Fcall::$variant(m) => m.tag,)*
+                    $(Fcall::$xvariant(m) => m.tag,)*
+                }
+            }
+
+            /// The size of the message as declared in its header.
+            pub fn size(&self) -> u32 {
+                match self {
+                    $(Fcall::$variant(m) => m.size,)*
+                    $(Fcall::$xvariant(m) => m.size,)*
+                }
+            }
+        }
+
+        // Taking a particular message out of an Fcall gives the Fcall back
+        // if it holds some other message.
+        $(impl TryFrom<Fcall> for $ty {
+            type Error = Fcall;
+
+            fn try_from(m: Fcall) -> Result<Self, Fcall> {
+                match m {
+                    Fcall::$variant(m) => Ok(m),
+                    m => Err(m),
+                }
+            }
+        })*
+        $(impl TryFrom<Fcall> for $xty {
+            type Error = Fcall;
+
+            fn try_from(m: Fcall) -> Result<Self, Fcall> {
+                match m {
+                    Fcall::$xvariant(m) => Ok(m),
+                    m => Err(m),
+                }
+            }
+        })*
+    };
+}
+
+fcall! {
+    base {
+        Rlerror(Rlerror),
+        Tstatfs(Tstatfs),
+        Rstatfs(Rstatfs),
+        Tlopen(Tlopen),
+        Rlopen(Rlopen),
+        Tlcreate(Tlcreate),
+        Rlcreate(Rlcreate),
+        Tsymlink(Tsymlink),
+        Rsymlink(Rsymlink),
+        Tmknod(Tmknod),
+        Rmknod(Rmknod),
+        Trename(Trename),
+        Rrename(Rrename),
+        Treadlink(Treadlink),
+        Rreadlink(Rreadlink),
+        Tgetattr(Tgetattr),
+        Rgetattr(Rgetattr),
+        Tsetattr(Tsetattr),
+        Rsetattr(Rsetattr),
+        Txattrwalk(Txattrwalk),
+        Rxattrwalk(Rxattrwalk),
+        Txattrcreate(Txattrcreate),
+        Rxattrcreate(Rxattrcreate),
+        Treaddir(Treaddir),
+        Rreaddir(Rreaddir),
+        Tfsync(Tfsync),
+        Rfsync(Rfsync),
+        Tlock(Tlock),
+        Rlock(Rlock),
+        Tgetlock(Tgetlock),
+        Rgetlock(Rgetlock),
+        Tlink(Tlink),
+        Rlink(Rlink),
+        Tmkdir(Tmkdir),
+        Rmkdir(Rmkdir),
+        Trenameat(Trenameat),
+        Rrenameat(Rrenameat),
+        Tunlinkat(Tunlinkat),
+        Runlinkat(Runlinkat),
+        Tversion(Tversion),
+        Rversion(Rversion),
+        Tauth(Tauth),
+        Rauth(Rauth),
+        Tattach(Tattach),
+        Rattach(Rattach),
+        Rerror(Rerror),
+        Tflush(Tflush),
+        Rflush(Rflush),
+        Twalk(Twalk),
+        Rwalk(Rwalk),
+        Topen(Topen),
+        Ropen(Ropen),
+        Tcreate(Tcreate),
+        Rcreate(Rcreate),
+        Tread(Tread),
+        Rread(Rread),
+        Twrite(Twrite),
+        Rwrite(Rwrite),
+        Tclunk(Tclunk),
+        Rclunk(Rclunk),
+        Tremove(Tremove),
+        Rremove(Rremove),
+        Tstat(Tstat),
+        Rstat(Rstat),
+        Twstat(Twstat),
+        Rwstat(Rwstat),
+    }
+    extended {
+        RerrorU(RerrorU) => Rerror,
+        TcreateU(TcreateU) => Tcreate,
+        TstatU(TstatU) => Tstat,
+        RstatU(RstatU) => Rstat,
+        TwstatU(TwstatU) => Twstat,
+        TauthClassic(TauthClassic) => Tauth,
+        TattachClassic(TattachClassic) => Tattach,
+    }
+}
+
+impl Fcall {
+    /// Decode a single 9P2000.L message, or a classic 9P2000 message that
+    /// 9P2000.L left as it was, of any type.
+    pub fn decode(data: &[u8]) -> Result<Self, P9Error> {
+        Self::decode_version(&P9Version::V2000L, data)
+    }
+
+    /// Decode a single message of any type for a connection that negotiated
+    /// `version`. 9P2000.u extends some of the classic messages, and
+    /// 9P2000.L keeps its extended Tauth and Tattach.
+    pub fn decode_version(
+        version: &P9Version,
+        data: &[u8],
+    ) -> Result<Self, P9Error> {
+        // size[4] type[1] ...
+        let typ = match data.get(size_of::<u32>()) {
+            Some(b) => MessageType::try_from(*b)
+                .map_err(|_| P9Error::UnknownMessageType(*b))?,
+            None => return Err(P9Error::ShortMessage(data.len())),
+        };
+        if *version == P9Version::V2000U {
+            match typ {
+                MessageType::Rerror => {
+                    return Ok(Fcall::RerrorU(ispf::from_bytes_le(data)?))
+                }
+                MessageType::Tcreate => {
+                    return Ok(Fcall::TcreateU(ispf::from_bytes_le(data)?))
+                }
+                MessageType::Tstat => {
+                    return Ok(Fcall::TstatU(ispf::from_bytes_le(data)?))
+                }
+                MessageType::Rstat => {
+                    return Ok(Fcall::RstatU(ispf::from_bytes_le(data)?))
+                }
+                MessageType::Twstat => {
+                    return Ok(Fcall::TwstatU(ispf::from_bytes_le(data)?))
+                }
+                _ => {}
+            }
+        }
+        if *version == P9Version::V2000 {
+            match typ {
+                MessageType::Tauth => {
+                    return Ok(Fcall::TauthClassic(ispf::from_bytes_le(data)?))
+                }
+                MessageType::Tattach => {
+                    return Ok(Fcall::TattachClassic(ispf::from_bytes_le(
+                        data,
+                    )?))
+                }
+                _ => {}
+            }
+        }
+        Self::decode_base(typ, data)
     }
 }
@@ -0,0 +1,30 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use p9ds::auth::{AuthHandler, SharedSecret};
+use p9ds::errno::Errno;
+
+#[test]
+fn shared_secret_in_pieces() {
+    let auth = SharedSecret::new(b"sesame".to_vec());
+    let mut session = auth.start("user", "", 0).unwrap();
+    assert_eq!(auth.write(&mut session, 0, b"ses"), Ok(3));
+    assert!(!auth.verify(&session, "user", ""));
+    assert_eq!(auth.write(&mut session, 3, b"ame"), Ok(3));
+    assert!(auth.verify(&session, "user", ""));
+}
+
+#[test]
+fn shared_secret_rejects_bad_offsets() {
+    let auth = SharedSecret::new(b"sesame".to_vec());
+    let mut session = auth.start("user", "", 0).unwrap();
+    let eacces = Err(Errno::EACCES.linux());
+    assert_eq!(auth.write(&mut session, 1, b"esame"), eacces);
+    // Adding the length to an offset this large would overflow.
+    assert_eq!(auth.write(&mut session, u64::MAX - 1, b"sesame"), eacces);
+    assert_eq!(auth.write(&mut session, 0, b"sesame!"), eacces);
+    assert!(session.is_empty());
+}
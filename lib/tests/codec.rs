@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use bytes::BytesMut;
+use p9ds::codec::P9Codec;
+use p9ds::error::P9Error;
+use p9ds::proto::{Rclunk, Rread, Tclunk};
+use tokio_util::codec::{Decoder, Encoder};
+
+#[test]
+fn split_message() {
+    let mut codec = P9Codec::default();
+    let mut wire = BytesMut::new();
+    codec.encode(&Rread::new(vec![7; 100]), &mut wire).unwrap();
+
+    let mut src = BytesMut::new();
+    src.extend_from_slice(&wire[..3]);
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    src.extend_from_slice(&wire[3..50]);
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    src.extend_from_slice(&wire[50..]);
+    assert_eq!(codec.decode(&mut src).unwrap().unwrap(), wire);
+    assert!(src.is_empty());
+}
+
+#[test]
+fn merged_messages() {
+    let mut codec = P9Codec::default();
+    let mut src = BytesMut::new();
+    codec.encode(&Tclunk::new(1), &mut src).unwrap();
+    codec.encode(&Rclunk::new(), &mut src).unwrap();
+    src.extend_from_slice(&[0x20]);
+
+    let first = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(first.len(), Tclunk::new(1).size as usize);
+    let second = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(second.len(), Rclunk::new().size as usize);
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    assert_eq!(&src[..], &[0x20]);
+}
+
+#[test]
+fn msize_limit() {
+    let mut codec = P9Codec::new(64);
+    let big = Rread::new(vec![0; 100]);
+
+    let mut dst = BytesMut::new();
+    assert!(matches!(
+        codec.encode(&big, &mut dst),
+        Err(P9Error::MessageTooLarge { .. })
+    ));
+
+    let mut src = BytesMut::new();
+    P9Codec::default().encode(&big, &mut src).unwrap();
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(P9Error::MessageTooLarge { .. })
+    ));
+}
@@ -0,0 +1,57 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use p9ds::proto::*;
+
+fn pair<T: Request>() -> (MessageType, MessageType) {
+    (T::message_type(), T::Response::message_type())
+}
+
+/// Every R-message type is numbered one past the T-message it answers.
+#[test]
+fn responses_follow_requests() {
+    for (t, r) in [
+        pair::<Tversion>(),
+        pair::<Tclunk>(),
+        pair::<Tgetattr>(),
+        pair::<Tsetattr>(),
+        pair::<Txattrwalk>(),
+        pair::<Txattrcreate>(),
+        pair::<Tstatfs>(),
+        pair::<Tauth>(),
+        pair::<Tattach>(),
+        pair::<Tflush>(),
+        pair::<Twalk>(),
+        pair::<Tlopen>(),
+        pair::<Treaddir>(),
+        pair::<Tread>(),
+        pair::<Twrite>(),
+        pair::<Tlcreate>(),
+        pair::<Tsymlink>(),
+        pair::<Tmknod>(),
+        pair::<Trename>(),
+        pair::<Treadlink>(),
+        pair::<Tfsync>(),
+        pair::<Tlock>(),
+        pair::<Tgetlock>(),
+        pair::<Tlink>(),
+        pair::<Tmkdir>(),
+        pair::<Trenameat>(),
+        pair::<Tunlinkat>(),
+        pair::<Tremove>(),
+        pair::<Topen>(),
+        pair::<Tcreate>(),
+        pair::<Tstat>(),
+        pair::<Twstat>(),
+        pair::<TcreateU>(),
+        pair::<TstatU>(),
+        pair::<TwstatU>(),
+        pair::<TauthClassic>(),
+        pair::<TattachClassic>(),
+    ] {
+        assert_eq!(u8::from(r), u8::from(t) + 1, "{t} answered by {r}");
+    }
+}
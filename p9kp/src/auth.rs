@@ -0,0 +1,126 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Authenticated attach.
+//!
+//! Before attaching, a client may ask the server for an authentication fid
+//! with Tauth. The client and server then run an authentication protocol of
+//! their choosing by reading and writing that afid, and the afid is finally
+//! handed to Tattach as proof of identity.
+
+use crate::Client;
+use async_trait::async_trait;
+use p9ds::error::P9Error;
+use p9ds::proto::{
+    P9Version, Rattach, Tattach, TattachClassic, Tauth, TauthClassic, Tclunk,
+    Twrite,
+};
+
+#[async_trait]
+pub trait Authenticator {
+    /// Run the client side of the authentication exchange over `afid`, which
+    /// the server has just established with Rauth. No single read or write
+    /// may carry more than `count` bytes.
+    async fn authenticate<C: Client + Send>(
+        &self,
+        client: &mut C,
+        afid: u32,
+        count: u32,
+    ) -> Result<(), P9Error>;
+}
+
+/// Attach `fid` to the file tree `aname` as `uname`, first authenticating
+/// over `afid` with `auth`, reading and writing at most `count` bytes at a
+/// time. The afid is clunked once the attach is done.
+#[allow(clippy::too_many_arguments)]
+pub async fn attach<C, A>(
+    client: &mut C,
+    auth: &A,
+    fid: u32,
+    afid: u32,
+    uname: String,
+    aname: String,
+    n_uname: u32,
+    count: u32,
+) -> Result<Rattach, P9Error>
+where
+    C: Client + Send,
+    A: Authenticator + Sync,
+{
+    if client.version() == P9Version::V2000 {
+        let tauth = TauthClassic::new(afid, uname.clone(), aname.clone());
+        client.send(&tauth).await?;
+    } else {
+        let tauth = Tauth::new(afid, uname.clone(), aname.clone(), n_uname);
+        client.send(&tauth).await?;
+    }
+
+    let result = match auth.authenticate(client, afid, count).await {
+        Ok(()) => tattach(client, fid, afid, uname, aname, n_uname).await,
+        Err(e) => Err(e),
+    };
+
+    let clunk = Tclunk::new(afid);
+    client.send(&clunk).await?;
+    result
+}
+
+/// Attach `fid` with the form of Tattach the client's version has. Classic
+/// 9P2000 has no `n_uname`, so it is left out there.
+pub(crate) async fn tattach<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    afid: u32,
+    uname: String,
+    aname: String,
+    n_uname: u32,
+) -> Result<Rattach, P9Error> {
+    if client.version() == P9Version::V2000 {
+        let attach = TattachClassic::new(fid, afid, uname, aname);
+        client.send(&attach).await
+    } else {
+        let attach = Tattach::new(fid, afid, uname, aname, n_uname);
+        client.send(&attach).await
+    }
+}
+
+/// Authenticates by writing a secret shared with the server to the afid.
+/// This pairs with `p9ds::auth::SharedSecret` on the server side.
+pub struct SharedSecret {
+    secret: Vec<u8>,
+}
+
+impl SharedSecret {
+    pub fn new(secret: Vec<u8>) -> Self {
+        SharedSecret { secret }
+    }
+}
+
+#[async_trait]
+impl Authenticator for SharedSecret {
+    async fn authenticate<C: Client + Send>(
+        &self,
+        client: &mut C,
+        afid: u32,
+        count: u32,
+    ) -> Result<(), P9Error> {
+        let mut offset = 0;
+        while offset < self.secret.len() {
+            let end = usize::min(offset + count as usize, self.secret.len());
+            let write = Twrite::new(
+                self.secret[offset..end].to_vec(),
+                afid,
+                offset as u64,
+            );
+            let resp = client.send(&write).await?;
+            if resp.count == 0 {
+                return Err(P9Error::Protocol("short write to afid".into()));
+            }
+            offset += resp.count as usize;
+        }
+        Ok(())
+    }
+}
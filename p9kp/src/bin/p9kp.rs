@@ -7,21 +7,23 @@
 use async_recursion::async_recursion;
 use clap::{AppSettings, Parser};
 use devinfo::{get_devices, DiPropValue};
+use futures::StreamExt;
+use p9ds::errno::Errno;
+use p9ds::error::P9Error;
 use p9ds::proto::{
-    OpenFlags, P9Version, QidType, Rattach, Rgetattr, Rlopen, Rread, Rreaddir,
-    Rwalk, Tattach, Tgetattr, Tlopen, Tread, Treaddir, Twalk, Version, Wname,
-    NO_AFID, NO_NUNAME, P9_GETATTR_MODE,
+    OpenFlags, P9Version, Tgetattr, Tread, Tstat, TstatU, NO_NUNAME,
+    P9_DMSETGID, P9_DMSETUID, P9_DT_DIR, P9_GETATTR_MODE,
 };
-use p9kp::{ChardevClient, Client, UnixClient};
-use slog::{info, Drain, Logger};
+use p9kp::auth::SharedSecret;
+use p9kp::fid::Fid;
+use p9kp::session::Session;
+use p9kp::transport::{StreamTransport, TcpOptions, Transport};
+use slog::{info, warn, Drain, Logger};
 use std::error::Error;
 use std::fs::OpenOptions;
-use std::io::Write;
-use std::marker::Send;
+use std::io::{self, Write};
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
-
-const HEADER_SPACE: u32 = 11;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[clap(
@@ -34,6 +36,10 @@ struct Opts {
 
     #[clap(short, long, default_value_t = 65536)]
     chunk_size: u32,
+
+    /// Authenticate to the server using the shared secret in this file.
+    #[clap(long)]
+    auth_secret: Option<PathBuf>,
 }
 
 #[derive(Parser)]
@@ -44,8 +50,10 @@ enum SubCommand {
 #[derive(Parser)]
 #[clap(setting = AppSettings::InferSubcommands)]
 struct Pull {
-    /// Connect to a unix domain socket. If not specified the program will
-    /// use the first virtio filesystem device it can find.
+    /// Connect to a unix domain socket, to a TCP server given as
+    /// tcp://host:port, or on Linux to a vsock server given as
+    /// vsock://cid:port. If not specified the program will use the first
+    /// virtio filesystem device it can find.
     conn_str: Option<String>,
 }
 
@@ -69,24 +77,69 @@ async fn pull(
     p: &Pull,
     log: &Logger,
 ) -> Result<(), Box<dyn Error>> {
-    match p.conn_str {
-        None => {
-            let mut client = find_virtfs_dev(log).await?;
-            run(opts, &mut client, log).await?;
-        }
+    let session = match p.conn_str {
+        None => find_virtfs_dev(opts, log).await?,
         Some(ref conn_str) => {
-            let pb = PathBuf::from(conn_str);
-            let mut client = UnixClient::new(pb, log.clone());
-            run(opts, &mut client, log).await?;
+            let session =
+                Session::new(connect(conn_str, log).await?, log.clone());
+            negotiate(opts, &session, log).await?;
+            session
         }
     };
+    run(opts, &session, log).await
+}
 
+/// Agree on a protocol version with the server, and on an msize of at most
+/// the chunk size.
+async fn negotiate(
+    opts: &Opts,
+    session: &Session,
+    log: &Logger,
+) -> Result<(), P9Error> {
+    let (version, _) = session.negotiate(opts.chunk_size).await?;
+    info!(log, "negotiated {}", version.to_string());
     Ok(())
 }
 
+async fn connect(
+    conn_str: &str,
+    log: &Logger,
+) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+    if let Some(addr) = conn_str.strip_prefix("tcp://") {
+        let options = TcpOptions::default();
+        let transport =
+            StreamTransport::tcp(addr, &options, log.clone()).await?;
+        Ok(Box::new(transport))
+    } else if let Some(addr) = conn_str.strip_prefix("vsock://") {
+        connect_vsock(addr, log).await
+    } else {
+        let transport = StreamTransport::unix(conn_str, log.clone()).await?;
+        Ok(Box::new(transport))
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn connect_vsock(
+    addr: &str,
+    log: &Logger,
+) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+    let addr = p9kp::vsock::parse_addr(addr)?;
+    let transport = StreamTransport::vsock(addr, log.clone()).await?;
+    Ok(Box::new(transport))
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn connect_vsock(
+    _addr: &str,
+    _log: &Logger,
+) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+    Err("vsock connections are only supported on Linux".into())
+}
+
 async fn find_virtfs_dev(
+    opts: &Opts,
     log: &Logger,
-) -> Result<ChardevClient, Box<dyn Error>> {
+) -> Result<Session, Box<dyn Error>> {
     let devices = get_devices(false)?;
 
     // look for libvirt/vritfs device
@@ -117,191 +170,107 @@ async fn find_virtfs_dev(
                 device_key.node_name, unit_address,
             );
             info!(log, "trying path {} ...", dev_path);
-            let pb = PathBuf::from(dev_path);
-            let mut client = p9kp::ChardevClient::new(pb, 0x10000, log.clone());
-
-            let mut ver = Version::new(P9Version::V2000L);
-            ver.msize = 0x10000;
-            let server_version =
-                client.send::<Version, Version>(&ver).await.unwrap();
-            if Some(P9Version::V2000L)
-                == P9Version::from_str(&server_version.version)
-            {
-                info!(log, "compatible 9p device found");
-                return Ok(client);
-            } else {
-                info!(
-                    log,
-                    "not a compatible 9p device: {}", server_version.version
-                );
+            match probe(opts, PathBuf::from(dev_path), log).await {
+                Ok(session) => {
+                    info!(log, "compatible 9p device found");
+                    return Ok(session);
+                }
+                // keep looking ...
+                Err(e) => info!(log, "not a compatible 9p device: {}", e),
             }
-            // keep looking ...
         }
     }
     Err("suitable 9pfs device not found".into())
 }
 
-async fn run<C: Client + Send>(
+/// Open the 9P device at `path` and negotiate with the server behind it.
+async fn probe(
     opts: &Opts,
-    client: &mut C,
+    path: PathBuf,
     log: &Logger,
-) -> Result<(), Box<dyn Error>> {
-    let mut ver = Version::new(P9Version::V2000L);
-    ver.msize = opts.chunk_size;
-    client.send::<Version, Version>(&ver).await?;
-
-    let attach = Tattach::new(
-        1,
-        NO_AFID,
-        "root".into(),
-        "/todo".into(), //TODO not really used
-        NO_NUNAME,
-    );
-    client.send::<Tattach, Rattach>(&attach).await?;
-
-    let walk = Twalk::new(1, 2, Vec::new());
-    client.send::<Twalk, Rwalk>(&walk).await?;
-
-    let open = Tlopen::new(2, OpenFlags::RdOnly as u32);
-    client.send::<Tlopen, Rlopen>(&open).await?;
+) -> Result<Session, P9Error> {
+    let transport =
+        StreamTransport::chardev(path, opts.chunk_size, log.clone())?;
+    let session = Session::new(Box::new(transport), log.clone());
+    negotiate(opts, &session, log).await?;
+    Ok(session)
+}
 
-    let mut offset = 0;
-    let fid = 2;
-    let mut nextfid = 3;
-    let max_msg_size = opts.chunk_size - HEADER_SPACE;
-    loop {
-        let readdir = Treaddir::new(2, offset, max_msg_size);
-        let resp = client.send::<Treaddir, Rreaddir>(&readdir).await?;
-        if resp.data.is_empty() {
-            break;
+async fn run(
+    opts: &Opts,
+    session: &Session,
+    log: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    let uname = String::from("root");
+    let aname = String::from("/todo"); //TODO not really used
+    let root = match opts.auth_secret {
+        Some(ref path) => {
+            let secret = SharedSecret::new(std::fs::read(path)?);
+            Fid::attach_auth(session, &secret, uname, aname, NO_NUNAME).await?
         }
-        offset += resp.data.len() as u64;
+        None => Fid::attach(session, uname, aname, NO_NUNAME).await?,
+    };
 
-        let path = PathBuf::from(".");
-        copydir(client, opts, resp, "".into(), fid, &mut nextfid, log, path)
-            .await?;
-        if readdir.size < max_msg_size {
-            break;
-        }
-    }
+    let mut dir = root.walk(&[]).await?;
+    dir.open(OpenFlags::RdOnly as u32).await?;
+
+    copydir(&dir, "".into(), log, PathBuf::from(".")).await?;
 
+    dir.close().await?;
+    root.close().await?;
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
 #[async_recursion]
-async fn copydir<C>(
-    client: &mut C,
-    opts: &Opts,
-    readdir: Rreaddir,
+async fn copydir(
+    dir: &Fid,
     indent: String,
-    fid: u32,
-    nextfid: &mut u32,
     log: &Logger,
     path: PathBuf,
-) -> Result<(), Box<dyn Error>>
-where
-    C: Client + Send,
-{
-    for entry in readdir.data {
-        let attrs = match entry.qid.typ {
-            QidType::Dir => "d",
-            _ => "-",
-        };
+) -> Result<(), Box<dyn Error>> {
+    let entries = dir.read_dir();
+    tokio::pin!(entries);
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let attrs = if entry.qid.typ.is_dir() { "d" } else { "-" };
         info!(log, "{}  {}{}", attrs, indent, entry.name);
 
-        // QEMU only sets entry.typ to the real value and uses glibc extension
-        // types (DT_*) to identify the entry type.
-        if entry.qid.typ == QidType::Dir || entry.typ == libc::DT_DIR {
+        // QEMU only sets entry.typ to the real value and uses Linux d_type
+        // values to identify the entry type.
+        if entry.qid.typ.is_dir() || entry.typ == P9_DT_DIR {
             if entry.name == "." || entry.name == ".." {
                 continue;
             }
 
-            let newfid = *nextfid;
-            let w = Twalk::new(
-                fid,
-                newfid,
-                vec![Wname {
-                    value: entry.name.clone(),
-                }],
-            );
-            *nextfid += 1;
-            client.send::<Twalk, Rwalk>(&w).await?;
-
-            let o = Tlopen::new(newfid, OpenFlags::RdOnly as u32);
-            client.send::<Tlopen, Rlopen>(&o).await?;
-
-            let mut offset = 0;
-            loop {
-                let chunk_size = opts.chunk_size - HEADER_SPACE;
-                let readdir = Treaddir::new(newfid, offset, chunk_size);
-                let d = client.send::<Treaddir, Rreaddir>(&readdir).await?;
-                if d.data.is_empty() {
-                    break;
-                }
-                offset += d.data.len() as u64;
-
-                let mut fp = path.clone();
-                fp.push(entry.name.clone());
-                std::fs::create_dir_all(format!("{}", fp.display()))?;
-
-                copydir(
-                    client,
-                    opts,
-                    d,
-                    format!("  {indent}"),
-                    newfid,
-                    nextfid,
-                    log,
-                    fp.clone(),
-                )
-                .await?;
-
-                if readdir.size < chunk_size {
-                    break;
-                }
-            }
-        } else if entry.qid.typ == QidType::File {
-            copyfile(
-                entry.name.clone(),
-                opts,
-                client,
-                fid,
-                nextfid,
-                log,
-                path.clone(),
-            )
-            .await?;
+            let mut subdir = dir.walk(&[&entry.name]).await?;
+            subdir.open(OpenFlags::RdOnly as u32).await?;
+
+            let mut fp = path.clone();
+            fp.push(entry.name.clone());
+            std::fs::create_dir_all(format!("{}", fp.display()))?;
+
+            copydir(&subdir, format!("  {indent}"), log, fp).await?;
+            subdir.close().await?;
+        } else if entry.qid.typ.is_file() {
+            copyfile(dir, entry.name.clone(), log, path.clone()).await?;
         }
     }
     Ok(())
 }
 
-async fn copyfile<C: Client>(
+async fn copyfile(
+    dir: &Fid,
     name: String,
-    opts: &Opts,
-    client: &mut C,
-    fid: u32,
-    nextfid: &mut u32,
-    _log: &Logger,
+    log: &Logger,
     path: PathBuf,
 ) -> Result<(), Box<dyn Error>> {
-    let newfid = *nextfid;
-    let walk = Twalk::new(
-        fid,
-        newfid,
-        vec![Wname {
-            value: name.clone(),
-        }],
-    );
-    *nextfid += 1;
-    client.send::<Twalk, Rwalk>(&walk).await?;
-
-    let open = Tlopen::new(newfid, OpenFlags::RdOnly as u32);
-    client.send::<Tlopen, Rlopen>(&open).await?;
-
-    let getattr = Tgetattr::new(newfid, P9_GETATTR_MODE);
-    let attr = client.send::<Tgetattr, Rgetattr>(&getattr).await?;
+    let mut fid = dir.walk(&[&name]).await?;
+    fid.open(OpenFlags::RdOnly as u32).await?;
+
+    let session = fid.session();
+    let count = session.read_size(fid.iounit());
+
+    let mode = permissions(&fid).await?;
 
     let mut fp = path.clone();
     fp.push(name.clone());
@@ -312,8 +281,8 @@ async fn copyfile<C: Client>(
 
     let mut offset = 0;
     loop {
-        let r = Tread::new(newfid, offset, opts.chunk_size - HEADER_SPACE);
-        let f = client.send::<Tread, Rread>(&r).await?;
+        let r = Tread::new(fid.id(), offset, count);
+        let f = session.request(&r).await?;
         if f.data.is_empty() {
             break;
         }
@@ -322,10 +291,103 @@ async fn copyfile<C: Client>(
         file.write_all(f.data.as_slice())?;
     }
 
-    std::fs::set_permissions(
-        &fp,
-        std::fs::Permissions::from_mode(attr.mode & 0o7777),
-    )?;
+    // Copy the extended attributes first, while the file is still ours to
+    // write whatever its permissions end up being.
+    copy_xattrs(&fid, &fp, log).await?;
+    std::fs::set_permissions(&fp, std::fs::Permissions::from_mode(mode))?;
+
+    fid.close().await?;
+    Ok(())
+}
+
+/// The permission bits of `fid`, from whichever attribute request the
+/// session's protocol version has.
+async fn permissions(fid: &Fid) -> Result<u32, P9Error> {
+    let session = fid.session();
+    let mode = match session.version() {
+        P9Version::V2000L => {
+            let getattr = Tgetattr::new(fid.id(), P9_GETATTR_MODE);
+            return Ok(session.request(&getattr).await?.mode & 0o7777);
+        }
+        P9Version::V2000U => {
+            session.request(&TstatU::new(fid.id())).await?.stat.mode
+        }
+        _ => session.request(&Tstat::new(fid.id())).await?.stat.mode,
+    };
+    let mut perm = mode & 0o777;
+    if mode & P9_DMSETUID != 0 {
+        perm |= 0o4000;
+    }
+    if mode & P9_DMSETGID != 0 {
+        perm |= 0o2000;
+    }
+    Ok(perm)
+}
 
+/// Copy the extended attributes of `fid` to the local file `path`. An
+/// attribute that cannot be set here, such as a security label without the
+/// privilege to set it, is skipped with a warning.
+async fn copy_xattrs(
+    fid: &Fid,
+    path: &Path,
+    log: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    // Extended attributes are a 9P2000.L extension.
+    if fid.session().version() != P9Version::V2000L {
+        return Ok(());
+    }
+    let names = match fid.list_xattrs().await {
+        Ok(names) => names,
+        // The server keeps no extended attributes.
+        Err(P9Error::Server(Errno::EOPNOTSUPP)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !names.is_empty() && !XATTR_SUPPORTED {
+        warn!(log, "not copying extended attributes of {}", path.display());
+        return Ok(());
+    }
+    for name in names {
+        let value = fid.get_xattr(&name).await?;
+        if let Err(e) = set_xattr(path, &name, &value) {
+            warn!(log, "{}: not setting {}: {}", path.display(), name, e);
+        }
+    }
     Ok(())
 }
+
+/// Whether `set_xattr` can set extended attributes on this platform.
+const XATTR_SUPPORTED: bool =
+    cfg!(target_os = "illumos") || xattr::SUPPORTED_PLATFORM;
+
+#[cfg(not(target_os = "illumos"))]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    xattr::set(path, name, value)
+}
+
+/// illumos keeps extended attributes as files in a hidden directory of the
+/// file they belong to, which `openat` reaches with `O_XATTR`.
+#[cfg(target_os = "illumos")]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let file = OpenOptions::new().write(true).open(path)?;
+    let name = CString::new(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let flags = libc::O_XATTR | libc::O_CREAT | libc::O_WRONLY | libc::O_TRUNC;
+    // Safety: `name` is a NUL-terminated string that outlives the call.
+    let fd = unsafe {
+        libc::openat(
+            file.as_raw_fd(),
+            name.as_ptr(),
+            flags,
+            0o666 as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: `fd` was just opened and nothing else owns it.
+    let mut attr = unsafe { std::fs::File::from_raw_fd(fd) };
+    attr.write_all(value)
+}
@@ -0,0 +1,171 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Asynchronous access to a 9P character device.
+//!
+//! The virtio 9P character device can only be used with blocking reads and
+//! writes, so each direction gets a dedicated thread that does the blocking
+//! I/O and talks to the async side over channels. The read thread reads in
+//! chunks as the device hands them out and leaves putting messages back
+//! together to the caller, the write thread writes each buffer in full.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+
+/// How many chunks the read thread may get ahead of the reader.
+const READ_AHEAD: usize = 8;
+
+/// Open the character device at `path` for exclusive use. Reads from the
+/// device are made `chunk_size` bytes at a time.
+pub fn open<P: AsRef<Path>>(
+    path: P,
+    chunk_size: usize,
+) -> io::Result<(ChardevReader, ChardevWriter)> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_EXCL)
+        .open(path)?;
+    let wfile = file.try_clone()?;
+
+    let (read_tx, read_rx) = mpsc::channel(READ_AHEAD);
+    let (write_tx, write_rx) = mpsc::unbounded_channel();
+    let write_err = read_tx.clone();
+    std::thread::Builder::new()
+        .name("p9kp-chardev-read".into())
+        .spawn(move || read_thread(file, chunk_size, read_tx))?;
+    std::thread::Builder::new()
+        .name("p9kp-chardev-write".into())
+        .spawn(move || write_thread(wfile, write_rx, write_err))?;
+
+    let reader = ChardevReader {
+        rx: read_rx,
+        chunk: Vec::new(),
+        pos: 0,
+        eof: false,
+    };
+    let writer = ChardevWriter { tx: write_tx };
+    Ok((reader, writer))
+}
+
+/// The read half of a character device. Reading returns end of file once
+/// the device does, and any error the device returns in either direction.
+pub struct ChardevReader {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl AsyncRead for ChardevReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.eof {
+            return Poll::Ready(Ok(()));
+        }
+        if self.pos == self.chunk.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Pending => return Poll::Pending,
+                // The read thread marks end of file with an empty chunk.
+                Poll::Ready(Some(Ok(chunk))) if chunk.is_empty() => {
+                    self.eof = true;
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = usize::min(buf.remaining(), self.chunk.len() - self.pos);
+        buf.put_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The write half of a character device. Each write is queued and handed
+/// to the device in a single call, so writing a whole message at a time
+/// keeps messages intact on the device.
+pub struct ChardevWriter {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl AsyncWrite for ChardevWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.tx.send(buf.to_vec()) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Runs until the device reports end of file or an error, or the reader is
+/// dropped. A read that is blocked in the device when the reader is dropped
+/// holds the thread until the device answers.
+fn read_thread(
+    mut file: File,
+    chunk_size: usize,
+    tx: mpsc::Sender<io::Result<Vec<u8>>>,
+) {
+    let mut buf = vec![0; chunk_size];
+    loop {
+        let result = match file.read(&mut buf) {
+            Ok(n) => Ok(buf[..n].to_vec()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        let done = !matches!(result, Ok(ref chunk) if !chunk.is_empty());
+        if tx.blocking_send(result).is_err() || done {
+            return;
+        }
+    }
+}
+
+/// Runs until the writer is dropped or a write fails. Write errors are
+/// reported to the reader, which is where a caller waits for the response.
+fn write_thread(
+    mut file: File,
+    mut rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    err: mpsc::Sender<io::Result<Vec<u8>>>,
+) {
+    while let Some(buf) = rx.blocking_recv() {
+        // write_all deals with short writes and EINTR.
+        if let Err(e) = file.write_all(&buf) {
+            let _ = err.blocking_send(Err(e));
+            return;
+        }
+    }
+}
@@ -0,0 +1,392 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Fid handles.
+//!
+//! A `Fid` owns one fid of a session from the time the server establishes it
+//! until it is clunked. Dropping a `Fid` clunks it in the background, and the
+//! fid number goes back to the session for reuse once the server has
+//! answered. `close` does the same but waits for the answer.
+
+use crate::auth::{self, Authenticator};
+use crate::session::Session;
+use crate::{walk, xattr};
+use futures::stream::{self, Stream};
+use p9ds::error::P9Error;
+use p9ds::proto::{
+    Dirent, OpenFlags, P9Version, Qid, Stat, StatU, Tclunk, Tcreate, TcreateU,
+    Tlcreate, Tlopen, Topen, Tread, Treaddir, NO_AFID, P9_DMDEVICE, P9_DMDIR,
+    P9_DMNAMEDPIPE, P9_DMSETGID, P9_DMSETUID, P9_DMSOCKET, P9_DMSYMLINK,
+    P9_DOTL_TRUNC, P9_DT_DIR, P9_DT_FIFO, P9_DT_LNK, P9_DT_REG, P9_DT_SOCK,
+    P9_DT_UNKNOWN, P9_ORDWR, P9_OREAD, P9_OTRUNC, P9_OWRITE,
+};
+use std::collections::VecDeque;
+
+// The access mode bits of Linux open flags.
+const O_ACCMODE: u32 = 0o3;
+
+pub struct Fid {
+    session: Session,
+    id: u32,
+    iounit: u32,
+    /// Whether the server knows about the fid, and so it must be clunked.
+    live: bool,
+}
+
+impl Fid {
+    /// Reserve a fid number that the server does not know about yet.
+    fn reserve(session: &Session) -> Result<Self, P9Error> {
+        Ok(Fid {
+            session: session.clone(),
+            id: session.alloc_fid()?,
+            iounit: 0,
+            live: false,
+        })
+    }
+
+    /// Attach to the file tree `aname` as `uname` without authenticating.
+    /// Returns the fid of the root of the tree. `n_uname` is not sent on a
+    /// classic 9P2000 session.
+    pub async fn attach(
+        session: &Session,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    ) -> Result<Self, P9Error> {
+        let mut fid = Self::reserve(session)?;
+        let mut client = session.clone();
+        auth::tattach(&mut client, fid.id, NO_AFID, uname, aname, n_uname)
+            .await?;
+        fid.live = true;
+        Ok(fid)
+    }
+
+    /// Attach to the file tree `aname` as `uname`, authenticating with
+    /// `auth` first. Returns the fid of the root of the tree.
+    pub async fn attach_auth<A>(
+        session: &Session,
+        auth: &A,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    ) -> Result<Self, P9Error>
+    where
+        A: Authenticator + Sync,
+    {
+        let mut fid = Self::reserve(session)?;
+        // auth::attach clunks the afid whatever happens.
+        let afid = session.alloc_fid()?;
+        let mut client = session.clone();
+        let result = auth::attach(
+            &mut client,
+            auth,
+            fid.id,
+            afid,
+            uname,
+            aname,
+            n_uname,
+            session.max_write(),
+        )
+        .await;
+        session.free_fid(afid);
+        result?;
+        fid.live = true;
+        Ok(fid)
+    }
+
+    /// The fid number, for requests that have no method here.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The iounit the server gave when the fid was opened, zero if it was
+    /// not opened or the server set no iounit.
+    pub fn iounit(&self) -> u32 {
+        self.iounit
+    }
+
+    /// Walk a new fid from this one through each of `names` in turn. An
+    /// empty `names` makes a new fid for the same file.
+    pub async fn walk(&self, names: &[&str]) -> Result<Fid, P9Error> {
+        let mut fid = Self::reserve(&self.session)?;
+        walk(&mut self.session.clone(), self.id, fid.id, names).await?;
+        fid.live = true;
+        Ok(fid)
+    }
+
+    /// Open the file for I/O with Linux open `flags`. Before 9P2000.L only
+    /// the access mode and `P9_DOTL_TRUNC` carry over.
+    pub async fn open(&mut self, flags: u32) -> Result<Qid, P9Error> {
+        let (qid, iounit) = if self.session.version() == P9Version::V2000L {
+            let resp =
+                self.session.request(&Tlopen::new(self.id, flags)).await?;
+            (resp.qid, resp.iounit)
+        } else {
+            let open = Topen::new(self.id, open_mode(flags));
+            let resp = self.session.request(&open).await?;
+            (resp.qid, resp.iounit)
+        };
+        self.iounit = iounit;
+        Ok(qid)
+    }
+
+    /// Create the file `name` in this directory and open it with Linux open
+    /// `flags`. The fid refers to the new file afterwards. Before 9P2000.L
+    /// `gid` is not sent, the server picks the group, and `mode` carries over
+    /// like `flags` do to `open`: the permission bits, and on 9P2000.u the
+    /// setuid and setgid bits.
+    pub async fn create(
+        &mut self,
+        name: String,
+        flags: u32,
+        mode: u32,
+        gid: u32,
+    ) -> Result<Qid, P9Error> {
+        let version = self.session.version();
+        let (qid, iounit) = match version {
+            P9Version::V2000L => {
+                let create = Tlcreate::new(self.id, name, flags, mode, gid);
+                let resp = self.session.request(&create).await?;
+                (resp.qid, resp.iounit)
+            }
+            P9Version::V2000U => {
+                let perm = create_perm(version, mode);
+                let create = TcreateU::new(
+                    self.id,
+                    name,
+                    perm,
+                    open_mode(flags),
+                    String::new(),
+                );
+                let resp = self.session.request(&create).await?;
+                (resp.qid, resp.iounit)
+            }
+            _ => {
+                let perm = create_perm(version, mode);
+                let create =
+                    Tcreate::new(self.id, name, perm, open_mode(flags));
+                let resp = self.session.request(&create).await?;
+                (resp.qid, resp.iounit)
+            }
+        };
+        self.iounit = iounit;
+        Ok(qid)
+    }
+
+    /// Read the entries of the directory, which must have been opened.
+    pub fn read_dir(
+        &self,
+    ) -> impl Stream<Item = Result<Dirent, P9Error>> + Send + 'static {
+        read_dir(&self.session, self.id, self.iounit)
+    }
+
+    /// List the names of the file's extended attributes.
+    pub async fn list_xattrs(&self) -> Result<Vec<String>, P9Error> {
+        let xfid = Self::reserve(&self.session)?;
+        let count = self.session.max_read();
+        let mut client = self.session.clone();
+        xattr::list(&mut client, self.id, xfid.id, count).await
+    }
+
+    /// Get the value of the file's extended attribute `name`.
+    pub async fn get_xattr(&self, name: &str) -> Result<Vec<u8>, P9Error> {
+        let xfid = Self::reserve(&self.session)?;
+        let count = self.session.max_read();
+        let mut client = self.session.clone();
+        xattr::get(&mut client, self.id, xfid.id, name, count).await
+    }
+
+    /// Set the file's extended attribute `name` to `value`. `flags` is
+    /// `P9_XATTR_CREATE`, `P9_XATTR_REPLACE` or 0, as for `xattr::set`.
+    pub async fn set_xattr(
+        &self,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+    ) -> Result<(), P9Error> {
+        let xfid = Self::reserve(&self.session)?;
+        let count = self.session.max_write();
+        let mut client = self.session.clone();
+        xattr::set(&mut client, self.id, xfid.id, name, value, flags, count)
+            .await
+    }
+
+    /// Remove the file's extended attribute `name`.
+    pub async fn remove_xattr(&self, name: &str) -> Result<(), P9Error> {
+        let xfid = Self::reserve(&self.session)?;
+        let mut client = self.session.clone();
+        xattr::remove(&mut client, self.id, xfid.id, name).await
+    }
+
+    /// Clunk the fid and wait for the server to answer. The fid is gone
+    /// afterwards even if the server reports an error.
+    pub async fn close(mut self) -> Result<(), P9Error> {
+        let result = self.session.request(&Tclunk::new(self.id)).await;
+        self.live = false;
+        result?;
+        Ok(())
+    }
+}
+
+/// The Topen mode for Linux open `flags`.
+fn open_mode(flags: u32) -> u8 {
+    let mut mode = if flags & O_ACCMODE == OpenFlags::WrOnly as u32 {
+        P9_OWRITE
+    } else if flags & O_ACCMODE == OpenFlags::RdWr as u32 {
+        P9_ORDWR
+    } else {
+        P9_OREAD
+    };
+    if flags & P9_DOTL_TRUNC != 0 {
+        mode |= P9_OTRUNC;
+    }
+    mode
+}
+
+/// The Tcreate permissions for Linux `mode`. Only 9P2000.u has setuid and
+/// setgid bits.
+fn create_perm(version: P9Version, mode: u32) -> u32 {
+    let mut perm = mode & 0o777;
+    if version == P9Version::V2000U {
+        if mode & 0o4000 != 0 {
+            perm |= P9_DMSETUID;
+        }
+        if mode & 0o2000 != 0 {
+            perm |= P9_DMSETGID;
+        }
+    }
+    perm
+}
+
+impl Drop for Fid {
+    fn drop(&mut self) {
+        if !self.live {
+            self.session.free_fid(self.id);
+            return;
+        }
+        // Without a runtime to clunk on, the fid is never reused.
+        if let Ok(rt) = tokio::runtime::Handle::try_current() {
+            let session = self.session.clone();
+            let id = self.id;
+            rt.spawn(async move {
+                let _ = session.request(&Tclunk::new(id)).await;
+                session.free_fid(id);
+            });
+        }
+    }
+}
+
+/// Read the entries of the directory opened as `fid` with `iounit`. On
+/// 9P2000.L, entries are fetched with as many Treaddir requests as it takes,
+/// each continuing from the offset the server gave the last entry so far.
+/// Older dialects read the directory as a file of stat records instead. The
+/// stream ends when the server has no more entries to give, or after the
+/// first error.
+fn read_dir(
+    session: &Session,
+    fid: u32,
+    iounit: u32,
+) -> impl Stream<Item = Result<Dirent, P9Error>> + Send + 'static {
+    let state = ReadDir {
+        session: session.clone(),
+        fid,
+        iounit,
+        offset: 0,
+        entries: VecDeque::new(),
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(entry) = state.entries.pop_front() {
+                return Some((Ok(entry), state));
+            }
+            if state.done {
+                return None;
+            }
+            match state.fetch().await {
+                Ok(entries) if entries.is_empty() => state.done = true,
+                Ok(entries) => state.entries.extend(entries),
+                Err(e) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+            }
+        }
+    })
+}
+
+struct ReadDir {
+    session: Session,
+    fid: u32,
+    iounit: u32,
+    offset: u64,
+    entries: VecDeque<Dirent>,
+    done: bool,
+}
+
+impl ReadDir {
+    /// The next entries after `offset`, none at the end of the directory.
+    async fn fetch(&mut self) -> Result<Vec<Dirent>, P9Error> {
+        let version = self.session.version();
+        if version == P9Version::V2000L {
+            let count = self.session.max_readdir();
+            let readdir = Treaddir::new(self.fid, self.offset, count);
+            let resp = self.session.request(&readdir).await?;
+            if let Some(last) = resp.data.last() {
+                self.offset = last.offset;
+            }
+            return Ok(resp.data);
+        }
+
+        // Reads of a directory return whole stat records, and continue at
+        // the byte offset the last read ended at.
+        let count = self.session.read_size(self.iounit);
+        let read = Tread::new(self.fid, self.offset, count);
+        let data = self.session.request(&read).await?.data;
+        self.offset += data.len() as u64;
+        let offset = self.offset;
+        let dirent = |qid, mode, name| Dirent {
+            qid,
+            offset,
+            typ: dirent_type(mode),
+            name,
+        };
+        Ok(if version == P9Version::V2000U {
+            StatU::decode_dir(&data)?
+                .into_iter()
+                .map(|s| dirent(s.qid, s.mode, s.name))
+                .collect()
+        } else {
+            Stat::decode_dir(&data)?
+                .into_iter()
+                .map(|s| dirent(s.qid, s.mode, s.name))
+                .collect()
+        })
+    }
+}
+
+/// The Dirent type of a file with 9P2000 or 9P2000.u `mode`.
+fn dirent_type(mode: u32) -> u8 {
+    if mode & P9_DMDIR != 0 {
+        P9_DT_DIR
+    } else if mode & P9_DMSYMLINK != 0 {
+        P9_DT_LNK
+    } else if mode & P9_DMNAMEDPIPE != 0 {
+        P9_DT_FIFO
+    } else if mode & P9_DMSOCKET != 0 {
+        P9_DT_SOCK
+    } else if mode & P9_DMDEVICE != 0 {
+        // Whether it is a block or character device is only in the stat
+        // extension string.
+        P9_DT_UNKNOWN
+    } else {
+        P9_DT_REG
+    }
+}
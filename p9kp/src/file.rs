@@ -0,0 +1,335 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Remote files as tokio I/O objects.
+//!
+//! A `RemoteFile` keeps a position in an open file and turns `AsyncRead`,
+//! `AsyncWrite` and `AsyncSeek` calls into Tread and Twrite requests at that
+//! position, no larger than the fid's iounit and the session's msize allow.
+//! One request is in flight at a time. Every read makes a single request and
+//! may transfer less than asked for, which the tokio extension traits deal
+//! with. A write takes a copy of as much as a single request can carry and
+//! returns straight away, as `tokio::fs::File` does; the next call waits for
+//! it and reports its error, if any. `read_at` and `write_at` make a single
+//! request at a given offset and leave the position alone.
+
+use crate::fid::Fid;
+use crate::fs::{self, Metadata};
+use futures::future::BoxFuture;
+use futures::ready;
+use p9ds::proto::{
+    SetAttrMask, Tfsync, Tgetattr, Tread, Tsetattr, Twrite, P9_GETATTR_SIZE,
+};
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+pub struct RemoteFile {
+    fid: Fid,
+    pos: u64,
+    op: Op,
+    seek: Option<SeekFrom>,
+}
+
+/// The request in flight, if any.
+enum Op {
+    Idle,
+    Read(BoxFuture<'static, io::Result<Vec<u8>>>),
+    /// Writing data the caller has already been told is written.
+    Write(BoxFuture<'static, io::Result<()>>),
+    /// Getting the file size to seek relative to the end.
+    Len(BoxFuture<'static, io::Result<u64>>, i64),
+}
+
+impl RemoteFile {
+    /// Do I/O on `fid`, which must have been opened, starting at the
+    /// beginning of the file. Files opened for appending are written at the
+    /// end whatever the position says.
+    pub fn new(fid: Fid) -> Self {
+        RemoteFile {
+            fid,
+            pos: 0,
+            op: Op::Idle,
+            seek: None,
+        }
+    }
+
+    pub fn fid(&self) -> &Fid {
+        &self.fid
+    }
+
+    /// Give the fid back. A request that is still in flight is abandoned,
+    /// so flush first to be sure what was written has reached the server.
+    pub fn into_fid(self) -> Fid {
+        self.fid
+    }
+
+    /// Read from `offset` into `buf`, returning how many bytes were read.
+    /// Zero means end of file, or an empty `buf`.
+    pub async fn read_at(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let read = tread(&self.fid, offset, buf.len());
+        let data = self.fid.session().request(&read).await?.data;
+        let n = usize::min(data.len(), buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    /// Write `buf` at `offset`, returning how many bytes were written.
+    pub async fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let write = twrite(&self.fid, buf, offset);
+        Ok(self.fid.session().request(&write).await?.count as usize)
+    }
+
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        fs::metadata(&self.fid).await
+    }
+
+    /// The offset the next read or write starts at.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Ask the server to commit the file's data and metadata to stable
+    /// storage, once any write in flight has finished.
+    pub async fn sync_all(&mut self) -> io::Result<()> {
+        self.sync(false).await
+    }
+
+    /// Like `sync_all`, but metadata that is not needed to read the data
+    /// back may be left for later.
+    pub async fn sync_data(&mut self) -> io::Result<()> {
+        self.sync(true).await
+    }
+
+    async fn sync(&mut self, datasync: bool) -> io::Result<()> {
+        self.finish().await?;
+        let fsync = Tfsync::new(self.fid.id(), datasync as u32);
+        self.fid.session().request(&fsync).await?;
+        Ok(())
+    }
+
+    /// Truncate or extend the file to `size` bytes. The position is left
+    /// where it is, even if that is now past the end.
+    pub async fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.finish().await?;
+        let setattr = Tsetattr::new(
+            self.fid.id(),
+            SetAttrMask::SIZE,
+            0,
+            0,
+            0,
+            size,
+            0,
+            0,
+            0,
+            0,
+        );
+        self.fid.session().request(&setattr).await?;
+        Ok(())
+    }
+
+    /// Clunk the fid and wait for the server to answer, once any write in
+    /// flight has finished.
+    pub async fn close(mut self) -> io::Result<()> {
+        self.finish().await?;
+        Ok(self.fid.close().await?)
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        futures::future::poll_fn(|cx| self.poll_finish(cx)).await
+    }
+
+    /// Wait for the request in flight to finish and account for it. A read
+    /// nobody waited for is dropped, it has no effect to account for.
+    fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = match &mut self.op {
+            Op::Idle | Op::Read(_) => Ok(()),
+            Op::Write(f) => ready!(f.as_mut().poll(cx)),
+            Op::Len(f, offset) => {
+                let offset = *offset;
+                let pos = ready!(f.as_mut().poll(cx))
+                    .and_then(|len| seek_to(len, offset));
+                pos.map(|pos| self.pos = pos)
+            }
+        };
+        self.op = Op::Idle;
+        Poll::Ready(result)
+    }
+
+    fn start_read(&mut self, len: usize) {
+        let session = self.fid.session().clone();
+        let read = tread(&self.fid, self.pos, len);
+        self.op = Op::Read(Box::pin(async move {
+            Ok(session.request(&read).await?.data)
+        }));
+    }
+
+    /// Start writing as much of `buf` as one request can carry at the
+    /// position, and move the position past it. A short write is followed
+    /// by another for the rest.
+    fn start_write(&mut self, buf: &[u8]) -> usize {
+        let session = self.fid.session().clone();
+        let mut write = twrite(&self.fid, buf, self.pos);
+        let n = write.data.len();
+        self.pos += n as u64;
+        self.op = Op::Write(Box::pin(async move {
+            loop {
+                let count = session.request(&write).await?.count as usize;
+                if count == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                let count = usize::min(count, write.data.len());
+                if count == write.data.len() {
+                    return Ok(());
+                }
+                let rest = write.data[count..].to_vec();
+                write =
+                    Twrite::new(rest, write.fid, write.offset + count as u64);
+            }
+        }));
+        n
+    }
+
+    fn start_len(&mut self, offset: i64) {
+        let session = self.fid.session().clone();
+        let getattr = Tgetattr::new(self.fid.id(), P9_GETATTR_SIZE);
+        self.op = Op::Len(
+            Box::pin(
+                async move { Ok(session.request(&getattr).await?.attrsize) },
+            ),
+            offset,
+        );
+    }
+}
+
+/// A Tread of up to `len` bytes of `fid` from `offset`, no more than a
+/// single request can carry.
+fn tread(fid: &Fid, offset: u64, len: usize) -> Tread {
+    let count = usize::min(len, fid.session().read_size(fid.iounit()) as usize);
+    Tread::new(fid.id(), offset, count as u32)
+}
+
+/// A Twrite of as much of `buf` as a single request can carry to `fid` at
+/// `offset`.
+fn twrite(fid: &Fid, buf: &[u8], offset: u64) -> Twrite {
+    let count =
+        usize::min(buf.len(), fid.session().write_size(fid.iounit()) as usize);
+    Twrite::new(buf[..count].to_vec(), fid.id(), offset)
+}
+
+/// The position `offset` bytes from `base`.
+fn seek_to(base: u64, offset: i64) -> io::Result<u64> {
+    let pos = if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    };
+    pos.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Op::Read(f) = &mut this.op {
+                let result = ready!(f.as_mut().poll(cx));
+                this.op = Op::Idle;
+                // The caller may have come back with a smaller buffer than
+                // the read was made for, the rest is read again next time.
+                let data = result?;
+                let n = usize::min(data.len(), buf.remaining());
+                buf.put_slice(&data[..n]);
+                this.pos += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            ready!(this.poll_finish(cx))?;
+            if buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            this.start_read(buf.remaining());
+        }
+    }
+}
+
+impl AsyncWrite for RemoteFile {
+    /// Only returns `Pending` while an earlier write is still in flight, so
+    /// nothing of `buf` has been taken when it does.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_finish(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        Poll::Ready(Ok(this.start_write(buf)))
+    }
+
+    /// Waits for the write in flight and reports its error, if any. It does
+    /// not sync the file, `sync_all` does.
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_finish(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_finish(cx)
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if this.seek.is_some() || matches!(this.op, Op::Len(..)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a seek is already in progress, call poll_complete first",
+            ));
+        }
+        this.seek = Some(position);
+        Ok(())
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_finish(cx))?;
+            match this.seek.take() {
+                None => return Poll::Ready(Ok(this.pos)),
+                Some(SeekFrom::Start(pos)) => this.pos = pos,
+                Some(SeekFrom::Current(offset)) => {
+                    this.pos = seek_to(this.pos, offset)?
+                }
+                // Only the server knows where the end is.
+                Some(SeekFrom::End(offset)) => this.start_len(offset),
+            }
+        }
+    }
+}
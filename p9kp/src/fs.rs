@@ -0,0 +1,481 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! A path based view of a remote file tree.
+//!
+//! `RemoteFs` follows `std::fs`: each operation takes a path relative to the
+//! root the tree was attached at, walks to it, does its work and clunks the
+//! fids it used. Errors are `std::io::Error`s, with server errnos translated
+//! to the host's. It needs a 9P2000.L session, which `RemoteFs::new` checks.
+
+use crate::fid::Fid;
+use crate::file::RemoteFile;
+use async_recursion::async_recursion;
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use p9ds::proto::{
+    OpenFlags, P9Version, Qid, Rgetattr, Rstatfs, SetAttrMask, Tgetattr,
+    Tmkdir, Treadlink, Trenameat, Tsetattr, Tstatfs, Tsymlink, Tunlinkat,
+    P9_AT_REMOVEDIR, P9_DOTL_CREATE, P9_DOTL_TRUNC, P9_DT_DIR, P9_DT_LNK,
+    P9_GETATTR_BASIC, P9_MAXWELEM,
+};
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// File types as Linux puts them in st_mode, which is what 9P2000.L servers
+// send whatever the host's own values are.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+pub struct RemoteFs {
+    root: Fid,
+    gid: u32,
+}
+
+impl RemoteFs {
+    /// Use the tree attached as `root`. Files and directories are created
+    /// with group `gid`. The session must have negotiated 9P2000.L.
+    pub fn new(root: Fid, gid: u32) -> io::Result<Self> {
+        let version = root.session().version();
+        if version != P9Version::V2000L {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("RemoteFs needs 9P2000.L, not {}", version.to_string()),
+            ));
+        }
+        Ok(RemoteFs { root, gid })
+    }
+
+    pub fn root(&self) -> &Fid {
+        &self.root
+    }
+
+    /// Clunk the root fid and wait for the server to answer.
+    pub async fn close(self) -> io::Result<()> {
+        Ok(self.root.close().await?)
+    }
+
+    /// Walk a new fid to `path`. Symbolic links along the way are not
+    /// followed; the server gives them no meaning in a walk.
+    pub async fn walk<P: AsRef<Path>>(&self, path: P) -> io::Result<Fid> {
+        let names = names(path.as_ref())?;
+        let mut fid = self.root.walk(&[]).await?;
+        for chunk in names.chunks(P9_MAXWELEM) {
+            fid = fid.walk(chunk).await?;
+        }
+        Ok(fid)
+    }
+
+    /// Walk to the directory that holds `path` and return it along with the
+    /// last name in `path`.
+    async fn walk_parent(&self, path: &Path) -> io::Result<(Fid, String)> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| invalid_path(path))?
+            .to_str()
+            .ok_or_else(|| invalid_path(path))?;
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok((self.walk(parent).await?, name.to_string()))
+    }
+
+    /// The attributes of the file at `path`. Unlike `std::fs::metadata` a
+    /// symbolic link at `path` is not followed.
+    pub async fn metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<Metadata> {
+        metadata(&self.walk(path).await?).await
+    }
+
+    /// The entries of the directory at `path`, without "." and "..".
+    pub async fn read_dir<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<impl Stream<Item = io::Result<DirEntry>> + Send + 'static>
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut dir = self.walk(&path).await?;
+        dir.open(OpenFlags::RdOnly as u32).await?;
+        let entries = dir.read_dir();
+        Ok(entries.filter_map(move |entry| {
+            // The stream owns the directory fid so it stays open for as
+            // long as there may be entries left to read.
+            let _dir = &dir;
+            let entry = match entry {
+                Ok(e) if e.name == "." || e.name == ".." => None,
+                Ok(e) => Some(Ok(DirEntry {
+                    path: path.join(&e.name),
+                    name: e.name,
+                    qid: e.qid,
+                    typ: e.typ,
+                })),
+                Err(e) => Some(Err(e.into())),
+            };
+            future::ready(entry)
+        }))
+    }
+
+    /// Open the file at `path` for reading.
+    pub async fn open<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<RemoteFile> {
+        self.open_with(path, OpenFlags::RdOnly as u32).await
+    }
+
+    /// Open the file at `path` with Linux open `flags`. Flags that create
+    /// the file are not honoured, use `create_with` for those.
+    pub async fn open_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        flags: u32,
+    ) -> io::Result<RemoteFile> {
+        let mut fid = self.walk(path).await?;
+        fid.open(flags).await?;
+        Ok(RemoteFile::new(fid))
+    }
+
+    /// Open the file at `path` for writing, creating it if it does not
+    /// exist and truncating it if it does.
+    pub async fn create<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<RemoteFile> {
+        let flags = OpenFlags::WrOnly as u32 | P9_DOTL_CREATE | P9_DOTL_TRUNC;
+        self.create_with(path, flags, 0o666).await
+    }
+
+    /// Create and open the file at `path` with Linux open `flags` and
+    /// permission bits `mode`.
+    pub async fn create_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        flags: u32,
+        mode: u32,
+    ) -> io::Result<RemoteFile> {
+        let (mut fid, name) = self.walk_parent(path.as_ref()).await?;
+        fid.create(name, flags | P9_DOTL_CREATE, mode, self.gid)
+            .await?;
+        Ok(RemoteFile::new(fid))
+    }
+
+    /// Create the directory `path`, whose parent must exist.
+    pub async fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (dir, name) = self.walk_parent(path.as_ref()).await?;
+        mkdir(&dir, name, self.gid).await
+    }
+
+    /// Create the directory `path` and any of its parents that are missing.
+    /// Directories that already exist are fine, anything else is an error.
+    pub async fn create_dir_all<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<()> {
+        let mut dir = self.root.walk(&[]).await?;
+        for name in names(path.as_ref())? {
+            dir = match dir.walk(&[name]).await {
+                Ok(fid) => fid,
+                Err(e) => {
+                    let e = io::Error::from(e);
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                    // Someone else may have made it in the meantime.
+                    match mkdir(&dir, name.to_string(), self.gid).await {
+                        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                            return Err(e)
+                        }
+                        _ => {}
+                    }
+                    dir.walk(&[name]).await?
+                }
+            };
+        }
+        if !metadata(&dir).await?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} exists and is not a directory",
+                    path.as_ref().display()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove the file or symbolic link at `path`.
+    pub async fn remove_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.unlink(path.as_ref(), 0).await
+    }
+
+    /// Remove the empty directory at `path`.
+    pub async fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.unlink(path.as_ref(), P9_AT_REMOVEDIR).await
+    }
+
+    /// Remove the directory at `path` after removing everything in it.
+    /// Symbolic links are removed, not followed.
+    pub async fn remove_dir_all<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<()> {
+        self.remove_tree(path.as_ref()).await
+    }
+
+    #[async_recursion]
+    async fn remove_tree(&self, path: &Path) -> io::Result<()> {
+        let entries = self.read_dir(path).await?;
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if entry.is_dir() {
+                self.remove_tree(entry.path()).await?;
+            } else {
+                self.remove_file(entry.path()).await?;
+            }
+        }
+        self.remove_dir(path).await
+    }
+
+    async fn unlink(&self, path: &Path, flags: u32) -> io::Result<()> {
+        let (dir, name) = self.walk_parent(path).await?;
+        let unlink = Tunlinkat::new(dir.id(), name, flags);
+        dir.session().request(&unlink).await?;
+        Ok(())
+    }
+
+    /// Rename `from` to `to`, replacing `to` if it exists.
+    pub async fn rename<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (olddir, oldname) = self.walk_parent(from.as_ref()).await?;
+        let (newdir, newname) = self.walk_parent(to.as_ref()).await?;
+        let rename = Trenameat::new(olddir.id(), oldname, newdir.id(), newname);
+        olddir.session().request(&rename).await?;
+        Ok(())
+    }
+
+    /// Create a symbolic link at `link` that points to `original`. The
+    /// target is stored as given and means whatever it means on the server.
+    pub async fn symlink<P, Q>(&self, original: P, link: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let original = original.as_ref();
+        let target = original.to_str().ok_or_else(|| invalid_path(original))?;
+        let (dir, name) = self.walk_parent(link.as_ref()).await?;
+        let symlink = Tsymlink::new(dir.id(), name, target.into(), self.gid);
+        dir.session().request(&symlink).await?;
+        Ok(())
+    }
+
+    /// The target of the symbolic link at `path`.
+    pub async fn read_link<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<PathBuf> {
+        let fid = self.walk(path).await?;
+        let resp = fid.session().request(&Treadlink::new(fid.id())).await?;
+        Ok(resp.target.into())
+    }
+
+    /// Change the permission bits of the file at `path`.
+    pub async fn set_permissions<P: AsRef<Path>>(
+        &self,
+        path: P,
+        perm: Permissions,
+    ) -> io::Result<()> {
+        let fid = self.walk(path).await?;
+        let setattr = Tsetattr::new(
+            fid.id(),
+            SetAttrMask::MODE,
+            perm.mode(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+        fid.session().request(&setattr).await?;
+        Ok(())
+    }
+
+    /// Statistics for the file system that holds `path`.
+    pub async fn statfs<P: AsRef<Path>>(&self, path: P) -> io::Result<Rstatfs> {
+        let fid = self.walk(path).await?;
+        Ok(fid.session().request(&Tstatfs::new(fid.id())).await?)
+    }
+}
+
+/// The names to walk through for `path`. Absolute paths are taken to start
+/// at the root of the tree.
+fn names(path: &Path) -> io::Result<Vec<&str>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => names.push(".."),
+            Component::Normal(name) => {
+                names.push(name.to_str().ok_or_else(|| invalid_path(path))?)
+            }
+        }
+    }
+    Ok(names)
+}
+
+fn invalid_path(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid path {}", path.display()),
+    )
+}
+
+async fn mkdir(dir: &Fid, name: String, gid: u32) -> io::Result<()> {
+    let mkdir = Tmkdir::new(dir.id(), name, 0o777, gid);
+    dir.session().request(&mkdir).await?;
+    Ok(())
+}
+
+pub(crate) async fn metadata(fid: &Fid) -> io::Result<Metadata> {
+    let getattr = Tgetattr::new(fid.id(), P9_GETATTR_BASIC);
+    let attr = fid.session().request(&getattr).await?;
+    Ok(Metadata { attr })
+}
+
+// Metadata ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// The attributes of a remote file.
+#[derive(Debug)]
+pub struct Metadata {
+    attr: Rgetattr,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.attr.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.attr.mode & S_IFMT == S_IFREG
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.attr.mode & S_IFMT == S_IFLNK
+    }
+
+    pub fn len(&self) -> u64 {
+        self.attr.attrsize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attr.attrsize == 0
+    }
+
+    /// The file type and permission bits, with Linux file type values.
+    pub fn mode(&self) -> u32 {
+        self.attr.mode
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_mode(self.attr.mode & !S_IFMT)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.attr.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.attr.gid
+    }
+
+    pub fn nlink(&self) -> u64 {
+        self.attr.nlink
+    }
+
+    pub fn qid(&self) -> &Qid {
+        &self.attr.qid
+    }
+
+    /// The last access time. As with `std::fs::Metadata`, this fails if the
+    /// time cannot be represented as a `SystemTime`.
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        time(self.attr.atime_sec, self.attr.atime_nsec)
+    }
+
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        time(self.attr.mtime_sec, self.attr.mtime_nsec)
+    }
+
+    /// Everything the server sent.
+    pub fn attr(&self) -> &Rgetattr {
+        &self.attr
+    }
+}
+
+/// The time `sec` seconds and `nsec` nanoseconds after the epoch. The
+/// seconds of a time before the epoch arrive as a negative number in two's
+/// complement, and the nanoseconds still count forward from them.
+fn time(sec: u64, nsec: u64) -> io::Result<SystemTime> {
+    let sec = sec as i64;
+    let time = if nsec >= 1_000_000_000 {
+        None
+    } else if sec >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(sec as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(sec.unsigned_abs()))
+    };
+    time.and_then(|t| t.checked_add(Duration::from_nanos(nsec)))
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "file time out of range")
+        })
+}
+
+// Directory entries ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// An entry of a directory read with `RemoteFs::read_dir`.
+#[derive(Debug)]
+pub struct DirEntry {
+    path: PathBuf,
+    name: String,
+    qid: Qid,
+    typ: u8,
+}
+
+impl DirEntry {
+    /// The path of the entry, the directory's path joined with its name.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn qid(&self) -> &Qid {
+        &self.qid
+    }
+
+    // Some servers, QEMU among them, only set the entry type and leave the
+    // qid type bits clear, so both are checked.
+
+    pub fn is_dir(&self) -> bool {
+        self.qid.typ.is_dir() || self.typ == P9_DT_DIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.qid.typ.is_symlink() || self.typ == P9_DT_LNK
+    }
+}
@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! POSIX advisory record locking.
+//!
+//! A 9P2000.L server never parks a Tlock waiting for a conflicting lock to be
+//! released. It answers BLOCKED and leaves it to the client to try again, so
+//! the blocking `lock` here polls the server with a backoff until the lock is
+//! granted.
+
+use crate::Client;
+use p9ds::error::P9Error;
+use p9ds::proto::{
+    LockStatus, LockType, Rgetlock, Tgetlock, Tlock, P9_LOCK_FLAGS_BLOCK,
+};
+use std::time::Duration;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Acquire a `lock_type` lock on `length` bytes of `fid` starting at `start`,
+/// waiting for as long as the range is held by someone else. A `length` of 0
+/// extends the range to the end of the file. `proc_id` and `client_id`
+/// identify the lock owner to the server.
+pub async fn lock<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    lock_type: LockType,
+    start: u64,
+    length: u64,
+    proc_id: u32,
+    client_id: &str,
+) -> Result<(), P9Error> {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let status = request(
+            client,
+            fid,
+            lock_type,
+            P9_LOCK_FLAGS_BLOCK,
+            start,
+            length,
+            proc_id,
+            client_id,
+        )
+        .await?;
+        match status {
+            LockStatus::Success => return Ok(()),
+            LockStatus::Blocked => {
+                tokio::time::sleep(backoff).await;
+                backoff = Duration::min(backoff * 2, MAX_BACKOFF);
+            }
+            s => return Err(P9Error::Lock(s)),
+        }
+    }
+}
+
+/// Like `lock`, but returns `false` instead of waiting if the range is held
+/// by someone else.
+pub async fn try_lock<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    lock_type: LockType,
+    start: u64,
+    length: u64,
+    proc_id: u32,
+    client_id: &str,
+) -> Result<bool, P9Error> {
+    let status =
+        request(client, fid, lock_type, 0, start, length, proc_id, client_id)
+            .await?;
+    match status {
+        LockStatus::Success => Ok(true),
+        LockStatus::Blocked => Ok(false),
+        s => Err(P9Error::Lock(s)),
+    }
+}
+
+/// Release any locks held by the owner on the given range of `fid`.
+pub async fn unlock<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    start: u64,
+    length: u64,
+    proc_id: u32,
+    client_id: &str,
+) -> Result<(), P9Error> {
+    let status = request(
+        client,
+        fid,
+        LockType::Unlck,
+        0,
+        start,
+        length,
+        proc_id,
+        client_id,
+    )
+    .await?;
+    match status {
+        LockStatus::Success => Ok(()),
+        s => Err(P9Error::Lock(s)),
+    }
+}
+
+/// Test whether a `lock_type` lock could be placed on the given range of
+/// `fid`. If it could, the returned lock type is `LockType::Unlck`, otherwise
+/// the response describes one of the conflicting locks.
+pub async fn getlock<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    lock_type: LockType,
+    start: u64,
+    length: u64,
+    proc_id: u32,
+    client_id: &str,
+) -> Result<Rgetlock, P9Error> {
+    let getlock =
+        Tgetlock::new(fid, lock_type, start, length, proc_id, client_id.into());
+    client.send(&getlock).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn request<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    lock_type: LockType,
+    flags: u32,
+    start: u64,
+    length: u64,
+    proc_id: u32,
+    client_id: &str,
+) -> Result<LockStatus, P9Error> {
+    let lock = Tlock::new(
+        fid,
+        lock_type,
+        flags,
+        start,
+        length,
+        proc_id,
+        client_id.into(),
+    );
+    let resp = client.send(&lock).await?;
+    Ok(resp.status)
+}
@@ -0,0 +1,499 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! 9P sessions.
+//!
+//! A `Session` is everything protocol related on top of a `Transport`:
+//! version negotiation, fid and tag allocation, and typed requests. It is a
+//! cheaply cloneable handle to a single connection that can have many
+//! requests outstanding at once. Each request is given a free tag, and a
+//! background reader task hands every response to the request waiting on its
+//! tag. Writes go through a writer task so that a request abandoned part way
+//! can never leave half a message on the wire.
+//!
+//! A request that is abandoned after it was sent keeps its tag until the
+//! server answers a Tflush for it, as the tag may not be reused before then.
+
+use crate::transport::Transport;
+use crate::{read_msg, Client};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use p9ds::codec::{frame_tag, set_frame_tag, P9Codec};
+use p9ds::error::P9Error;
+use p9ds::proto::{
+    MessageType, P9Version, Request, Rflush, Rread, Rreaddir, Rversion, Tflush,
+    Tversion, Twrite, NO_FID, NO_TAG,
+};
+use slog::{debug, trace, warn, Logger};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio_util::codec::Encoder;
+
+/// The default limit on outstanding requests per connection.
+pub const DEFAULT_MAX_REQUESTS: usize = 64;
+
+#[derive(Clone)]
+pub struct Session {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    log: Logger,
+    transport: Arc<dyn Transport>,
+    msize: AtomicU32,
+    version: Mutex<P9Version>,
+    requests: Arc<Semaphore>,
+    outgoing: mpsc::UnboundedSender<BytesMut>,
+    tags: Mutex<Tags>,
+    fids: Mutex<Fids>,
+}
+
+/// Tags in use, mapped to the waiter for the response if there still is
+/// one. `None` once the connection is closed.
+struct Tags {
+    inflight: Option<HashMap<u16, Option<oneshot::Sender<BytesMut>>>>,
+    next: u16,
+}
+
+/// Fids in use are those below `next` that are not on the free list.
+struct Fids {
+    next: u32,
+    free: Vec<u32>,
+}
+
+impl Session {
+    /// Start a session on `transport`. Must be called from within a tokio
+    /// runtime.
+    pub fn new(transport: Box<dyn Transport>, log: Logger) -> Self {
+        Self::with_max_requests(transport, DEFAULT_MAX_REQUESTS, log)
+    }
+
+    /// Start a session on `transport` that has at most `max_requests`
+    /// requests outstanding at once.
+    pub fn with_max_requests(
+        transport: Box<dyn Transport>,
+        max_requests: usize,
+        log: Logger,
+    ) -> Self {
+        let transport: Arc<dyn Transport> = Arc::from(transport);
+        let (outgoing, rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            log,
+            transport: transport.clone(),
+            msize: AtomicU32::new(P9Codec::default().msize()),
+            version: Mutex::new(P9Version::V2000L),
+            // Tags are 16 bits with NO_TAG set aside for Tversion, and each
+            // abandoned request needs a second tag for its Tflush.
+            requests: Arc::new(Semaphore::new(usize::min(
+                max_requests,
+                usize::from(NO_TAG) / 2,
+            ))),
+            outgoing,
+            tags: Mutex::new(Tags {
+                inflight: Some(HashMap::new()),
+                next: 0,
+            }),
+            fids: Mutex::new(Fids {
+                next: 0,
+                free: Vec::new(),
+            }),
+        });
+        // The tasks only hold on to the session weakly, so that dropping
+        // every handle drops the sender and the write loop with it. The read
+        // loop stops when the write loop does.
+        let (stop, stopped) = oneshot::channel();
+        tokio::spawn(write_loop(
+            Arc::downgrade(&shared),
+            transport.clone(),
+            rx,
+            stop,
+        ));
+        tokio::spawn(read_loop(Arc::downgrade(&shared), transport, stopped));
+        Session { shared }
+    }
+
+    /// Negotiate the protocol version with the server, preferring 9P2000.L
+    /// and falling back to 9P2000.u and then classic 9P2000 for servers that
+    /// refuse it. A server that answers "unknown" to all of them is rejected.
+    /// Messages are limited to the smaller of `msize` and the server's msize
+    /// from then on. Returns the agreed version along with the server's
+    /// Rversion.
+    pub async fn negotiate(
+        &self,
+        msize: u32,
+    ) -> Result<(P9Version, Rversion), P9Error> {
+        let mut offered = String::new();
+        for requested in
+            [P9Version::V2000L, P9Version::V2000U, P9Version::V2000]
+        {
+            let mut ver = Tversion::new(requested);
+            ver.msize = msize;
+            let resp = self.request(&ver).await?;
+            match P9Version::from_str(&resp.version) {
+                Some(
+                    v @ (P9Version::V2000L
+                    | P9Version::V2000U
+                    | P9Version::V2000),
+                ) => {
+                    let msize = u32::min(msize, resp.msize);
+                    if msize <= write_header() {
+                        return Err(P9Error::Protocol(format!(
+                            "msize {msize} leaves no room for data"
+                        )));
+                    }
+                    self.set_msize(msize);
+                    *self.shared.version.lock().unwrap() = v;
+                    return Ok((v, resp));
+                }
+                // The server answers "unknown" or a dialect we don't speak,
+                // try the next one down.
+                _ => offered = resp.version,
+            }
+        }
+        Err(P9Error::UnsupportedVersion(offered))
+    }
+
+    /// Pick a fid that is not in use. Fids are handed out as `Fid` handles,
+    /// which give them back when they are dropped.
+    pub(crate) fn alloc_fid(&self) -> Result<u32, P9Error> {
+        let mut fids = self.shared.fids.lock().unwrap();
+        match fids.free.pop() {
+            Some(fid) => Ok(fid),
+            // NO_FID is never handed out, there is no fid after it.
+            None if fids.next == NO_FID => {
+                Err(P9Error::Protocol("out of fids".into()))
+            }
+            None => {
+                let fid = fids.next;
+                fids.next += 1;
+                Ok(fid)
+            }
+        }
+    }
+
+    /// Make `fid` available again once it has been clunked or removed.
+    pub(crate) fn free_fid(&self, fid: u32) {
+        self.shared.fids.lock().unwrap().free.push(fid);
+    }
+
+    /// Send `t` and wait for the response. Unlike `Client::send` this only
+    /// needs a shared reference, so any number of requests can be made
+    /// concurrently through one handle.
+    pub async fn request<T: Request>(
+        &self,
+        t: &T,
+    ) -> Result<T::Response, P9Error> {
+        debug!(self.shared.log, "→ {:#?}", t);
+
+        let mut out = BytesMut::new();
+        self.codec().encode(t, &mut out)?;
+        // Version negotiation is always done with NO_TAG.
+        let tag = if T::message_type() == MessageType::Tversion {
+            Some(NO_TAG)
+        } else {
+            None
+        };
+
+        let permit = self
+            .shared
+            .requests
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| P9Error::NotConnected)?;
+        let (msg, tag) = self.shared.roundtrip(out, tag, Some(permit)).await?;
+
+        let r: T::Response = match read_msg(self.version(), &msg, tag) {
+            Ok(r) => r,
+            Err(e) => {
+                trace!(self.shared.log, "{:?}", &msg[..]);
+                return Err(e);
+            }
+        };
+        debug!(self.shared.log, "← {:?}", r);
+        Ok(r)
+    }
+
+    /// The limit on the size of messages, as agreed on by `negotiate`.
+    pub fn msize(&self) -> u32 {
+        self.shared.msize.load(Ordering::Relaxed)
+    }
+
+    /// The protocol version agreed on by `negotiate`, 9P2000.L until then.
+    pub fn version(&self) -> P9Version {
+        self.shared.version()
+    }
+
+    /// The most data a single Tread can ask for.
+    pub fn max_read(&self) -> u32 {
+        self.msize() - read_header()
+    }
+
+    /// The most data a single Twrite can carry.
+    pub fn max_write(&self) -> u32 {
+        self.msize() - write_header()
+    }
+
+    /// The most directory entry data a single Treaddir can ask for.
+    pub fn max_readdir(&self) -> u32 {
+        self.msize() - readdir_header()
+    }
+
+    /// The most data to read at once from a file that was opened with
+    /// `iounit`. A server sets the iounit of an open file when it can only
+    /// do smaller reads and writes than msize allows, zero means no limit.
+    pub fn read_size(&self, iounit: u32) -> u32 {
+        with_iounit(self.max_read(), iounit)
+    }
+
+    /// The most data to write at once to a file that was opened with
+    /// `iounit`.
+    pub fn write_size(&self, iounit: u32) -> u32 {
+        with_iounit(self.max_write(), iounit)
+    }
+
+    fn set_msize(&self, msize: u32) {
+        self.shared.msize.store(msize, Ordering::Relaxed);
+        self.shared.transport.set_msize(msize);
+    }
+
+    fn codec(&self) -> P9Codec {
+        P9Codec::new(self.msize())
+    }
+}
+
+#[async_trait]
+impl Client for Session {
+    async fn send<T: Request>(
+        &mut self,
+        t: &T,
+    ) -> Result<T::Response, P9Error> {
+        self.request(t).await
+    }
+
+    fn version(&self) -> P9Version {
+        self.shared.version()
+    }
+}
+
+impl Shared {
+    fn version(&self) -> P9Version {
+        *self.version.lock().unwrap()
+    }
+
+    /// Send `out` with a newly reserved tag, `tag` if given or else any
+    /// free one, and wait for the response.
+    async fn roundtrip(
+        self: &Arc<Self>,
+        mut out: BytesMut,
+        tag: Option<u16>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<(BytesMut, u16), P9Error> {
+        let (mut inflight, rx) = self.reserve(tag, permit)?;
+        set_frame_tag(&mut out, inflight.tag);
+        if self.outgoing.send(out).is_err() {
+            return Err(P9Error::NotConnected);
+        }
+        inflight.sent = true;
+
+        let msg = rx.await;
+        inflight.answered = true;
+        Ok((msg.map_err(|_| P9Error::NotConnected)?, inflight.tag))
+    }
+
+    fn reserve(
+        self: &Arc<Self>,
+        tag: Option<u16>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<(InFlight, oneshot::Receiver<BytesMut>), P9Error> {
+        let (tx, rx) = oneshot::channel();
+
+        let mut tags = self.tags.lock().unwrap();
+        let next = tags.next;
+        let inflight = tags.inflight.as_mut().ok_or(P9Error::NotConnected)?;
+        let tag = match tag {
+            Some(tag) if inflight.contains_key(&tag) => {
+                return Err(P9Error::Protocol(format!("tag {tag} in use")))
+            }
+            Some(tag) => tag,
+            // There are always free tags since the number of outstanding
+            // requests is limited well below the number of tags.
+            None => (0..NO_TAG)
+                .map(|i| next.wrapping_add(i) % NO_TAG)
+                .find(|t| !inflight.contains_key(t))
+                .unwrap(),
+        };
+        inflight.insert(tag, Some(tx));
+        if tag != NO_TAG {
+            tags.next = tag.wrapping_add(1) % NO_TAG;
+        }
+
+        let inflight = InFlight {
+            shared: self.clone(),
+            tag,
+            permit,
+            sent: false,
+            answered: false,
+        };
+        Ok((inflight, rx))
+    }
+
+    fn release(&self, tag: u16) {
+        let mut tags = self.tags.lock().unwrap();
+        if let Some(inflight) = tags.inflight.as_mut() {
+            inflight.remove(&tag);
+        }
+    }
+
+    /// Hand a response to the request waiting on its tag.
+    fn deliver(&self, msg: BytesMut) {
+        let tag = frame_tag(&msg);
+        let waiter = {
+            let mut tags = self.tags.lock().unwrap();
+            tags.inflight
+                .as_mut()
+                .and_then(|inflight| inflight.get_mut(&tag))
+                .and_then(Option::take)
+        };
+        let undelivered = match waiter {
+            Some(tx) => tx.send(msg).err(),
+            None => Some(msg),
+        };
+        if let Some(msg) = undelivered {
+            trace!(self.log, "discarding {:?}", &msg[..]);
+        }
+    }
+
+    /// Fail every outstanding and future request.
+    fn close(&self) {
+        self.tags.lock().unwrap().inflight = None;
+        self.requests.close();
+    }
+}
+
+/// A request that holds a tag. Dropping it before the response arrives
+/// flushes the request on the server.
+struct InFlight {
+    shared: Arc<Shared>,
+    tag: u16,
+    permit: Option<OwnedSemaphorePermit>,
+    sent: bool,
+    answered: bool,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if !self.sent || self.answered {
+            self.shared.release(self.tag);
+            return;
+        }
+        // Without a runtime to flush on, the tag is never reused.
+        if let Ok(rt) = tokio::runtime::Handle::try_current() {
+            let permit = self.permit.take();
+            rt.spawn(flush(self.shared.clone(), self.tag, permit));
+        }
+    }
+}
+
+/// Flush the abandoned request `oldtag` and then free its tag. The flush
+/// does not wait for a request slot, since it may be that every slot is held
+/// by an abandoned request.
+async fn flush(
+    shared: Arc<Shared>,
+    oldtag: u16,
+    _permit: Option<OwnedSemaphorePermit>,
+) {
+    debug!(shared.log, "flushing tag {}", oldtag);
+    let mut out = BytesMut::new();
+    let result = async {
+        P9Codec::default().encode(&Tflush::new(oldtag), &mut out)?;
+        let (msg, tag) = shared.roundtrip(out, None, None).await?;
+        read_msg::<Rflush>(shared.version(), &msg, tag)
+    }
+    .await;
+    match result {
+        Ok(_) => shared.release(oldtag),
+        Err(e) => warn!(shared.log, "flush of tag {} failed: {}", oldtag, e),
+    }
+}
+
+/// Runs until every handle to the session is gone or a write fails, then
+/// shuts the transport down.
+async fn write_loop(
+    shared: Weak<Shared>,
+    transport: Arc<dyn Transport>,
+    mut rx: mpsc::UnboundedReceiver<BytesMut>,
+    _stop: oneshot::Sender<()>,
+) {
+    while let Some(msg) = rx.recv().await {
+        if let Err(e) = transport.send(&msg).await {
+            if let Some(shared) = shared.upgrade() {
+                warn!(shared.log, "write failed: {}", e);
+                shared.close();
+            }
+            return;
+        }
+    }
+    // Nothing can be waiting for a response once the session is gone.
+    let _ = transport.shutdown().await;
+}
+
+/// Runs until the connection is closed, either by the server or by the
+/// write loop stopping.
+async fn read_loop(
+    shared: Weak<Shared>,
+    transport: Arc<dyn Transport>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    loop {
+        let result = tokio::select! {
+            result = transport.recv() => result,
+            _ = &mut stopped => return,
+        };
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        match result {
+            Ok(msg) => shared.deliver(msg),
+            Err(P9Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                debug!(shared.log, "eof");
+                shared.close();
+                return;
+            }
+            Err(e) => {
+                warn!(shared.log, "read failed: {}", e);
+                shared.close();
+                return;
+            }
+        }
+    }
+}
+
+// Everything in a message besides its data, the encoding of the message with
+// no data at all.
+
+fn read_header() -> u32 {
+    Rread::new(Vec::new()).size
+}
+
+fn write_header() -> u32 {
+    Twrite::new(Vec::new(), 0, 0).size
+}
+
+fn readdir_header() -> u32 {
+    Rreaddir::new(Vec::new()).size
+}
+
+fn with_iounit(max: u32, iounit: u32) -> u32 {
+    match iounit {
+        0 => max,
+        iounit => u32::min(max, iounit),
+    }
+}
@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Message transports.
+//!
+//! A transport moves whole 9P messages between client and server and knows
+//! nothing else about the protocol; tags, fids and message contents are the
+//! business of the `Session` on top. Sending and receiving take a shared
+//! reference so that a session can do both at once from separate tasks.
+
+use crate::chardev::{self, ChardevReader, ChardevWriter};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use p9ds::codec::{P9Codec, DEFAULT_MSIZE};
+use p9ds::error::P9Error;
+use slog::{trace, Logger};
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{tcp, unix, TcpStream, UnixStream};
+use tokio::sync::Mutex;
+use tokio_util::codec::Decoder;
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Send one complete message. A send that is cancelled part way may
+    /// leave part of the message on the wire.
+    async fn send(&self, frame: &[u8]) -> Result<(), P9Error>;
+
+    /// Receive the next complete message. Cancelling a receive loses
+    /// nothing, the next one picks up where it left off.
+    async fn recv(&self) -> Result<BytesMut, P9Error>;
+
+    /// Stop sending. The peer sees end of file once it has read everything
+    /// sent before.
+    async fn shutdown(&self) -> Result<(), P9Error>;
+
+    /// Reject received messages larger than `msize` from now on.
+    fn set_msize(&self, msize: u32);
+}
+
+// Stream transport ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// Messages over a byte stream split into read and write halves. This
+/// covers every transport that is a byte stream, whatever it is underneath.
+pub struct StreamTransport<R, W> {
+    reader: Mutex<FrameReader<R>>,
+    writer: Mutex<W>,
+    msize: AtomicU32,
+    log: Logger,
+}
+
+struct FrameReader<R> {
+    reader: R,
+    codec: P9Codec,
+    buf: BytesMut,
+}
+
+impl<R, W> StreamTransport<R, W>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    pub fn new(reader: R, writer: W, log: Logger) -> Self {
+        StreamTransport {
+            reader: Mutex::new(FrameReader {
+                reader,
+                codec: P9Codec::default(),
+                buf: BytesMut::new(),
+            }),
+            writer: Mutex::new(writer),
+            msize: AtomicU32::new(DEFAULT_MSIZE),
+            log,
+        }
+    }
+}
+
+impl StreamTransport<unix::OwnedReadHalf, unix::OwnedWriteHalf> {
+    /// Connect to a server on a unix domain socket.
+    pub async fn unix<P: AsRef<Path>>(
+        path: P,
+        log: Logger,
+    ) -> Result<Self, P9Error> {
+        let (reader, writer) = UnixStream::connect(path).await?.into_split();
+        Ok(Self::new(reader, writer, log))
+    }
+}
+
+impl StreamTransport<tcp::OwnedReadHalf, tcp::OwnedWriteHalf> {
+    /// Connect to a server over TCP.
+    pub async fn tcp(
+        addr: &str,
+        options: &TcpOptions,
+        log: Logger,
+    ) -> Result<Self, P9Error> {
+        let (reader, writer) = options.connect(addr).await?.into_split();
+        Ok(Self::new(reader, writer, log))
+    }
+}
+
+impl StreamTransport<ChardevReader, ChardevWriter> {
+    /// Open a 9P character device. Reads from the device are made
+    /// `chunk_size` bytes at a time.
+    pub fn chardev<P: AsRef<Path>>(
+        path: P,
+        chunk_size: u32,
+        log: Logger,
+    ) -> Result<Self, P9Error> {
+        let (reader, writer) = chardev::open(path, chunk_size as usize)?;
+        Ok(Self::new(reader, writer, log))
+    }
+}
+
+#[async_trait]
+impl<R, W> Transport for StreamTransport<R, W>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    async fn send(&self, frame: &[u8]) -> Result<(), P9Error> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(frame).await?;
+        trace!(self.log, "wrote {}", frame.len());
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<BytesMut, P9Error> {
+        let mut r = self.reader.lock().await;
+        let r = &mut *r;
+        r.codec.set_msize(self.msize.load(Ordering::Relaxed));
+        loop {
+            if let Some(msg) = r.codec.decode(&mut r.buf)? {
+                return Ok(msg);
+            }
+            let n = r.reader.read_buf(&mut r.buf).await?;
+            trace!(self.log, "read {}", n);
+            if n == 0 {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), P9Error> {
+        self.writer.lock().await.shutdown().await?;
+        Ok(())
+    }
+
+    fn set_msize(&self, msize: u32) {
+        self.msize.store(msize, Ordering::Relaxed);
+    }
+}
+
+// TCP options ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// Socket options for TCP connections.
+#[derive(Debug, Clone)]
+pub struct TcpOptions {
+    /// Give up on connecting after this long.
+    pub connect_timeout: Option<Duration>,
+    /// Set TCP_NODELAY. 9P is request and response, so waiting to coalesce
+    /// small writes only adds latency.
+    pub nodelay: bool,
+    /// Send keepalive probes after the connection has been idle this long.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            connect_timeout: Some(Duration::from_secs(10)),
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl TcpOptions {
+    /// Connect to `addr`, which may be anything tokio can resolve such as
+    /// "host:port", and apply the options to the new socket.
+    pub async fn connect(&self, addr: &str) -> Result<TcpStream, P9Error> {
+        let connect = TcpStream::connect(addr);
+        let stream = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??,
+            None => connect.await?,
+        };
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(time);
+            SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(stream)
+    }
+}
@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! AF_VSOCK transport, on Linux only.
+//!
+//! A guest without a virtio 9P device can still reach a 9P server on its
+//! host over a vsock connection to `VMADDR_CID_HOST`. On a single Linux
+//! machine the loopback CID, `VMADDR_CID_LOCAL`, connects to a listener on
+//! the same machine, which is handy for testing.
+
+use crate::transport::StreamTransport;
+use p9ds::error::P9Error;
+use slog::Logger;
+use std::io;
+use tokio_vsock::{OwnedReadHalf, OwnedWriteHalf, VsockStream};
+
+pub use tokio_vsock::{
+    VsockAddr, VsockListener, VMADDR_CID_ANY, VMADDR_CID_HOST, VMADDR_CID_LOCAL,
+};
+
+/// Parse a vsock address written as "cid:port".
+pub fn parse_addr(s: &str) -> io::Result<VsockAddr> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid vsock address {s:?}, expected cid:port"),
+        )
+    };
+    let (cid, port) = s.split_once(':').ok_or_else(invalid)?;
+    let cid = cid.parse().map_err(|_| invalid())?;
+    let port = port.parse().map_err(|_| invalid())?;
+    Ok(VsockAddr::new(cid, port))
+}
+
+/// Listen for vsock connections on `port` from any CID, including the
+/// loopback CID.
+pub fn listen(port: u32) -> io::Result<VsockListener> {
+    VsockListener::bind(VsockAddr::new(VMADDR_CID_ANY, port))
+}
+
+impl StreamTransport<OwnedReadHalf, OwnedWriteHalf> {
+    /// Connect to a server over vsock.
+    pub async fn vsock(addr: VsockAddr, log: Logger) -> Result<Self, P9Error> {
+        let (reader, writer) = VsockStream::connect(addr).await?.into_split();
+        Ok(Self::new(reader, writer, log))
+    }
+}
@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Extended attribute helpers.
+//!
+//! 9P2000.L does not have dedicated get/set messages for extended attributes.
+//! Instead a client walks to a special xattr fid with Txattrwalk (to read) or
+//! converts a fid with Txattrcreate (to write), moves the attribute value
+//! with ordinary Tread/Twrite messages and then clunks the xattr fid. For
+//! writes the clunk is what commits the value on the server.
+
+use crate::Client;
+use p9ds::error::P9Error;
+use p9ds::proto::{
+    Tclunk, Tread, Twalk, Twrite, Txattrcreate, Txattrwalk, P9_XATTR_REPLACE,
+};
+
+/// List the names of all extended attributes on `fid`. The fid `xfid` is
+/// used for the transfer and is clunked before returning. `count` is the
+/// maximum number of bytes requested per Tread.
+pub async fn list<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    xfid: u32,
+    count: u32,
+) -> Result<Vec<String>, P9Error> {
+    let data = get(client, fid, xfid, "", count).await?;
+    Ok(data
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Get the value of the extended attribute `name` on `fid`. The fid `xfid` is
+/// used for the transfer and is clunked before returning. `count` is the
+/// maximum number of bytes requested per Tread.
+pub async fn get<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    xfid: u32,
+    name: &str,
+    count: u32,
+) -> Result<Vec<u8>, P9Error> {
+    let walk = Txattrwalk::new(fid, xfid, name.into());
+    let resp = client.send(&walk).await?;
+
+    let result = read_value(client, xfid, resp.attrsize, count).await;
+    let clunk = Tclunk::new(xfid);
+    client.send(&clunk).await?;
+    result
+}
+
+/// Set the extended attribute `name` on `fid` to `value`. `flags` takes
+/// `P9_XATTR_CREATE` or `P9_XATTR_REPLACE` to require that the attribute does
+/// not or does already exist, or 0 for either. The fid `xfid` is used for the
+/// transfer and is clunked before returning. `count` is the maximum number of
+/// bytes sent per Twrite.
+pub async fn set<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    xfid: u32,
+    name: &str,
+    value: &[u8],
+    flags: u32,
+    count: u32,
+) -> Result<(), P9Error> {
+    let walk = Twalk::new(fid, xfid, Vec::new());
+    client.send(&walk).await?;
+
+    let create =
+        Txattrcreate::new(xfid, name.into(), value.len() as u64, flags);
+    let result = match client.send(&create).await {
+        Ok(_) => write_value(client, xfid, value, count).await,
+        Err(e) => Err(e),
+    };
+
+    // The server applies the attribute when the xattr fid is clunked.
+    let clunk = Tclunk::new(xfid);
+    client.send(&clunk).await?;
+    result
+}
+
+/// Remove the extended attribute `name` from `fid`. The fid `xfid` is used
+/// for the exchange and is clunked before returning.
+pub async fn remove<C: Client + Send>(
+    client: &mut C,
+    fid: u32,
+    xfid: u32,
+    name: &str,
+) -> Result<(), P9Error> {
+    // Like Linux v9fs, removal is a replacing create with an empty value.
+    set(client, fid, xfid, name, &[], P9_XATTR_REPLACE, 0).await
+}
+
+/// The largest attribute value or name list Linux allows. Larger sizes from
+/// a server are not to be trusted with an allocation.
+pub const XATTR_SIZE_MAX: u64 = 0x10000;
+
+async fn read_value<C: Client + Send>(
+    client: &mut C,
+    xfid: u32,
+    size: u64,
+    count: u32,
+) -> Result<Vec<u8>, P9Error> {
+    if size > XATTR_SIZE_MAX {
+        return Err(P9Error::Protocol(format!(
+            "xattr size {size} exceeds {XATTR_SIZE_MAX}"
+        )));
+    }
+    let mut data = Vec::new();
+    while (data.len() as u64) < size {
+        let remaining = size - data.len() as u64;
+        let count = u64::min(remaining, count.into()) as u32;
+        let read = Tread::new(xfid, data.len() as u64, count);
+        let resp = client.send(&read).await?;
+        if resp.data.is_empty() {
+            break;
+        }
+        data.extend_from_slice(resp.data.as_slice());
+    }
+    Ok(data)
+}
+
+async fn write_value<C: Client + Send>(
+    client: &mut C,
+    xfid: u32,
+    value: &[u8],
+    count: u32,
+) -> Result<(), P9Error> {
+    let mut offset = 0;
+    while offset < value.len() {
+        let end = usize::min(offset + count as usize, value.len());
+        let write =
+            Twrite::new(value[offset..end].to_vec(), xfid, offset as u64);
+        let resp = client.send(&write).await?;
+        if resp.count == 0 {
+            return Err(P9Error::Protocol("short xattr write".into()));
+        }
+        offset += resp.count as usize;
+    }
+    Ok(())
+}
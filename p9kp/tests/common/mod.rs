@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! A scripted server end of a session. Tests read the client's requests off
+//! it one at a time and answer them in whatever order they like.
+
+// Each test crate uses its own subset of this.
+#![allow(dead_code)]
+
+use p9ds::codec::set_frame_tag;
+use p9ds::proto::{Fcall, P9Version, Qid, QidType, Rattach, Rversion};
+use p9kp::fid::Fid;
+use p9kp::session::Session;
+use p9kp::transport::StreamTransport;
+use serde::Serialize;
+use slog::{o, Discard, Logger};
+use std::time::Duration;
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream,
+};
+
+/// How long a test waits on the client before giving up on it.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Server<S = DuplexStream> {
+    stream: S,
+}
+
+/// A session connected to a server that does nothing until told to.
+pub fn connect() -> (Session, Server) {
+    let (client, server) = tokio::io::duplex(0x10000);
+    let (reader, writer) = tokio::io::split(client);
+    let log = Logger::root(Discard, o!());
+    let transport = StreamTransport::new(reader, writer, log.clone());
+    (Session::new(Box::new(transport), log), Server::new(server))
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Server<S> {
+    /// Serve the client at the other end of `stream`.
+    pub fn new(stream: S) -> Self {
+        Server { stream }
+    }
+
+    /// The next request, along with its tag. `None` once the client has
+    /// shut the connection down.
+    pub async fn recv(&mut self) -> Option<(u16, Fcall)> {
+        self.recv_version(P9Version::V2000L).await
+    }
+
+    /// Like `recv`, decoding the request as `version` has it.
+    pub async fn recv_version(
+        &mut self,
+        version: P9Version,
+    ) -> Option<(u16, Fcall)> {
+        let read = async {
+            let mut size = [0u8; 4];
+            if self.stream.read_exact(&mut size).await.is_err() {
+                return None;
+            }
+            let mut msg = size.to_vec();
+            msg.resize(u32::from_le_bytes(size) as usize, 0);
+            self.stream.read_exact(&mut msg[4..]).await.unwrap();
+            let tag = u16::from_le_bytes([msg[5], msg[6]]);
+            Some((tag, Fcall::decode_version(&version, &msg).unwrap()))
+        };
+        tokio::time::timeout(TIMEOUT, read)
+            .await
+            .expect("no request from the client")
+    }
+
+    /// Answer the request with `tag`.
+    pub async fn reply<T: Serialize>(&mut self, tag: u16, msg: &T) {
+        let mut out = ispf::to_bytes_le(msg).unwrap();
+        set_frame_tag(&mut out, tag);
+        self.stream.write_all(&out).await.unwrap();
+    }
+
+    /// Answer Tversion requests, refusing each one until the client offers
+    /// `version`.
+    pub async fn agree(&mut self, version: P9Version) {
+        loop {
+            let (tag, t) = match self.recv().await {
+                Some((tag, Fcall::Tversion(t))) => (tag, t),
+                other => panic!("expected Tversion, got {:?}", other),
+            };
+            if P9Version::from_str(&t.version) == Some(version) {
+                self.reply(tag, &Rversion::new(t.msize, t.version)).await;
+                return;
+            }
+            self.reply(tag, &Rversion::new(t.msize, "unknown".into()))
+                .await;
+        }
+    }
+}
+
+/// Negotiate `version` and attach to the root of the server's tree.
+pub async fn attach(
+    session: &Session,
+    server: &mut Server,
+    version: P9Version,
+) -> Fid {
+    let (negotiated, ()) =
+        tokio::join!(session.negotiate(0x10000), server.agree(version));
+    assert_eq!(negotiated.unwrap().0, version);
+
+    let attach = Fid::attach(session, "root".into(), "".into(), 0);
+    let serve = async {
+        match server.recv_version(version).await {
+            Some((tag, Fcall::Tattach(_) | Fcall::TattachClassic(_))) => {
+                server.reply(tag, &Rattach::new(qid(QidType::DIR, 0))).await
+            }
+            other => panic!("expected Tattach, got {:?}", other),
+        }
+    };
+    let (root, ()) = tokio::join!(attach, serve);
+    root.unwrap()
+}
+
+pub fn qid(typ: QidType, path: u64) -> Qid {
+    Qid {
+        typ,
+        version: 0,
+        path,
+    }
+}
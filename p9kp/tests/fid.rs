@@ -0,0 +1,328 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Fid operations against a scripted server.
+
+mod common;
+
+use common::{attach, connect, qid, Server};
+use futures::StreamExt;
+use p9ds::proto::{
+    Dirent, Fcall, OpenFlags, P9Version, QidType, Rattach, Rauth, Rclunk,
+    Rcreate, Rlopen, Ropen, Rread, Rreaddir, Rwalk, Rwrite, Rxattrcreate,
+    StatU, P9_DMDIR, P9_DMSETGID, P9_DOTL_CREATE, P9_DOTL_TRUNC, P9_DT_DIR,
+    P9_DT_REG, P9_ORDWR, P9_OREAD, P9_OTRUNC,
+};
+use p9kp::auth::SharedSecret;
+use p9kp::fid::Fid;
+use std::time::Duration;
+
+fn stat_u(name: &str, typ: QidType, mode: u32) -> StatU {
+    StatU::new(
+        0,
+        0,
+        qid(typ, 1),
+        mode,
+        0,
+        0,
+        0,
+        name.into(),
+        "root".into(),
+        "root".into(),
+        "root".into(),
+        "".into(),
+        0,
+        0,
+        0,
+    )
+}
+
+fn dirent(name: &str, offset: u64) -> Dirent {
+    Dirent {
+        qid: qid(QidType::FILE, offset),
+        offset,
+        typ: P9_DT_REG,
+        name: name.into(),
+    }
+}
+
+#[tokio::test]
+async fn read_dir_pages() {
+    let (session, mut server) = connect();
+    let mut root = attach(&session, &mut server, P9Version::V2000L).await;
+
+    let open = root.open(OpenFlags::RdOnly as u32);
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Tlopen(_))) => {
+                server
+                    .reply(tag, &Rlopen::new(qid(QidType::DIR, 0), 0))
+                    .await
+            }
+            other => panic!("expected Tlopen, got {:?}", other),
+        }
+    };
+    let (open, ()) = tokio::join!(open, serve);
+    open.unwrap();
+
+    // Each Treaddir picks up at the offset of the last entry so far, and an
+    // empty reply ends the directory.
+    let pages = vec![
+        (0, vec![dirent("a", 1), dirent("b", 2)]),
+        (2, vec![dirent("c", 3)]),
+        (3, vec![]),
+    ];
+    let entries = root.read_dir().collect::<Vec<_>>();
+    let serve = async {
+        for (offset, page) in pages {
+            match server.recv().await {
+                Some((tag, Fcall::Treaddir(t))) => {
+                    assert_eq!(t.offset, offset);
+                    server.reply(tag, &Rreaddir::new(page)).await;
+                }
+                other => panic!("expected Treaddir, got {:?}", other),
+            }
+        }
+    };
+    let (entries, ()) = tokio::join!(entries, serve);
+    let names: Vec<_> = entries
+        .into_iter()
+        .map(|entry| entry.unwrap().name)
+        .collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn read_dir_9p2000u() {
+    let (session, mut server) = connect();
+    let mut root = attach(&session, &mut server, P9Version::V2000U).await;
+
+    let open = root.open(OpenFlags::RdOnly as u32);
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Topen(t))) => {
+                assert_eq!(t.mode, P9_OREAD);
+                server
+                    .reply(tag, &Ropen::new(qid(QidType::DIR, 0), 0))
+                    .await
+            }
+            other => panic!("expected Topen, got {:?}", other),
+        }
+    };
+    let (open, ()) = tokio::join!(open, serve);
+    open.unwrap();
+
+    // A directory reads as stat records, up to an empty read at the end.
+    let mut records =
+        ispf::to_bytes_le(&stat_u("sub", QidType::DIR, P9_DMDIR | 0o755))
+            .unwrap();
+    records.extend(
+        ispf::to_bytes_le(&stat_u("file", QidType::FILE, 0o644)).unwrap(),
+    );
+    let entries = root.read_dir().collect::<Vec<_>>();
+    let serve = async {
+        let mut offset = 0;
+        for data in [records.clone(), Vec::new()] {
+            match server.recv().await {
+                Some((tag, Fcall::Tread(t))) => {
+                    assert_eq!(t.offset, offset);
+                    offset += data.len() as u64;
+                    server.reply(tag, &Rread::new(data)).await;
+                }
+                other => panic!("expected Tread, got {:?}", other),
+            }
+        }
+    };
+    let (entries, ()) = tokio::join!(entries, serve);
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.name, entry.typ)
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        vec![("sub".into(), P9_DT_DIR), ("file".into(), P9_DT_REG)]
+    );
+}
+
+#[tokio::test]
+async fn attach_9p2000_has_no_n_uname() {
+    let (session, mut server) = connect();
+    let (negotiated, ()) = tokio::join!(
+        session.negotiate(0x10000),
+        server.agree(P9Version::V2000)
+    );
+    assert_eq!(negotiated.unwrap().0, P9Version::V2000);
+
+    let attach = Fid::attach(&session, "glenda".into(), "".into(), 0);
+    let serve = async {
+        match server.recv_version(P9Version::V2000).await {
+            Some((tag, Fcall::TattachClassic(t))) => {
+                assert_eq!(t.uname, "glenda");
+                server.reply(tag, &Rattach::new(qid(QidType::DIR, 0))).await
+            }
+            other => panic!("expected a classic Tattach, got {:?}", other),
+        }
+    };
+    let (root, ()) = tokio::join!(attach, serve);
+    root.unwrap();
+}
+
+/// Walk a new fid for the same file as `fid`, returning it with the number
+/// the server saw.
+async fn clone_fid(fid: &Fid, server: &mut Server) -> (Fid, u32) {
+    let walk = fid.walk(&[]);
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Twalk(t))) => {
+                server.reply(tag, &Rwalk::new(vec![])).await;
+                t.newfid
+            }
+            other => panic!("expected Twalk, got {:?}", other),
+        }
+    };
+    let (walk, newfid) = tokio::join!(walk, serve);
+    (walk.unwrap(), newfid)
+}
+
+#[tokio::test]
+async fn dropped_fid_is_clunked_and_reused() {
+    let (session, mut server) = connect();
+    let root = attach(&session, &mut server, P9Version::V2000L).await;
+
+    let (fid, newfid) = clone_fid(&root, &mut server).await;
+    assert_eq!(fid.id(), newfid);
+    drop(fid);
+    match server.recv().await {
+        Some((tag, Fcall::Tclunk(t))) => {
+            assert_eq!(t.fid, newfid);
+            server.reply(tag, &Rclunk::new()).await;
+        }
+        other => panic!("expected Tclunk, got {:?}", other),
+    }
+
+    // The number goes back to the session once the clunk is answered, which
+    // happens in the background.
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let (fid, reused) = clone_fid(&root, &mut server).await;
+    assert_eq!(reused, newfid);
+    assert_eq!(fid.id(), newfid);
+}
+
+#[tokio::test]
+async fn attach_auth_writes_secret_in_chunks() {
+    let (session, mut server) = connect();
+    let (negotiated, ()) =
+        tokio::join!(session.negotiate(64), server.agree(P9Version::V2000L));
+    negotiated.unwrap();
+
+    let secret = SharedSecret::new(vec![7; 100]);
+    let attach =
+        Fid::attach_auth(&session, &secret, "root".into(), "".into(), 0);
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Tauth(_))) => {
+                server.reply(tag, &Rauth::new(qid(QidType::AUTH, 0))).await
+            }
+            other => panic!("expected Tauth, got {:?}", other),
+        }
+        // No write may be larger than the msize allows.
+        let mut written = 0;
+        while written < 100 {
+            match server.recv().await {
+                Some((tag, Fcall::Twrite(t))) => {
+                    assert_eq!(t.offset, written);
+                    assert!(t.data.len() as u32 <= session.max_write());
+                    written += t.data.len() as u64;
+                    server.reply(tag, &Rwrite::new(t.data.len() as u32)).await;
+                }
+                other => panic!("expected Twrite, got {:?}", other),
+            }
+        }
+        for _ in 0..2 {
+            match server.recv().await {
+                Some((tag, Fcall::Tattach(_))) => {
+                    server.reply(tag, &Rattach::new(qid(QidType::DIR, 0))).await
+                }
+                Some((tag, Fcall::Tclunk(_))) => {
+                    server.reply(tag, &Rclunk::new()).await
+                }
+                other => panic!("expected Tattach or Tclunk, got {:?}", other),
+            }
+        }
+    };
+    let (root, ()) = tokio::join!(attach, serve);
+    root.unwrap();
+}
+
+#[tokio::test]
+async fn create_9p2000u() {
+    let (session, mut server) = connect();
+    let mut dir = attach(&session, &mut server, P9Version::V2000U).await;
+
+    let flags = OpenFlags::RdWr as u32 | P9_DOTL_CREATE | P9_DOTL_TRUNC;
+    let create = dir.create("new".into(), flags, 0o2644, 0);
+    let serve = async {
+        match server.recv_version(P9Version::V2000U).await {
+            Some((tag, Fcall::TcreateU(t))) => {
+                assert_eq!(t.name, "new");
+                assert_eq!(t.perm, P9_DMSETGID | 0o644);
+                assert_eq!(t.mode, P9_ORDWR | P9_OTRUNC);
+                let qid = qid(QidType::FILE, 1);
+                server.reply(tag, &Rcreate::new(qid, 4096)).await
+            }
+            other => panic!("expected Tcreate, got {:?}", other),
+        }
+    };
+    let (create, ()) = tokio::join!(create, serve);
+    assert_eq!(create.unwrap().path, 1);
+    assert_eq!(dir.iounit(), 4096);
+}
+
+#[tokio::test]
+async fn set_xattr_through_its_own_fid() {
+    let (session, mut server) = connect();
+    let root = attach(&session, &mut server, P9Version::V2000L).await;
+
+    let set = root.set_xattr("user.note", b"hello", 0);
+    let serve = async {
+        let xfid = match server.recv().await {
+            Some((tag, Fcall::Twalk(t))) => {
+                assert_eq!(t.fid, root.id());
+                server.reply(tag, &Rwalk::new(vec![])).await;
+                t.newfid
+            }
+            other => panic!("expected Twalk, got {:?}", other),
+        };
+        match server.recv().await {
+            Some((tag, Fcall::Txattrcreate(t))) => {
+                assert_eq!((t.fid, t.name.as_str()), (xfid, "user.note"));
+                assert_eq!(t.attrsize, 5);
+                server.reply(tag, &Rxattrcreate::new()).await
+            }
+            other => panic!("expected Txattrcreate, got {:?}", other),
+        }
+        match server.recv().await {
+            Some((tag, Fcall::Twrite(t))) => {
+                assert_eq!((t.fid, t.data.as_slice()), (xfid, &b"hello"[..]));
+                server.reply(tag, &Rwrite::new(5)).await
+            }
+            other => panic!("expected Twrite, got {:?}", other),
+        }
+        // The clunk commits the value.
+        match server.recv().await {
+            Some((tag, Fcall::Tclunk(t))) => {
+                assert_eq!(t.fid, xfid);
+                server.reply(tag, &Rclunk::new()).await
+            }
+            other => panic!("expected Tclunk, got {:?}", other),
+        }
+    };
+    let (set, ()) = tokio::join!(set, serve);
+    set.unwrap();
+}
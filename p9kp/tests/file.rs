@@ -0,0 +1,47 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! RemoteFile against a scripted server.
+
+mod common;
+
+use common::{attach, connect};
+use p9ds::proto::{Fcall, P9Version, Rwrite};
+use p9kp::file::RemoteFile;
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test]
+async fn write_returns_before_the_server_answers() {
+    let (session, mut server) = connect();
+    let root = attach(&session, &mut server, P9Version::V2000L).await;
+    let mut file = RemoteFile::new(root);
+
+    // The data is taken before the server has seen it, so nothing a later
+    // call passes can be mistaken for it.
+    assert_eq!(file.write(b"hello").await.unwrap(), 5);
+    assert_eq!(file.position(), 5);
+
+    let flush = file.flush();
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Twrite(t))) => {
+                assert_eq!((t.offset, t.data.as_slice()), (0, &b"hello"[..]));
+                server.reply(tag, &Rwrite::new(3)).await
+            }
+            other => panic!("expected Twrite, got {:?}", other),
+        }
+        // The rest of a short write follows on.
+        match server.recv().await {
+            Some((tag, Fcall::Twrite(t))) => {
+                assert_eq!((t.offset, t.data.as_slice()), (3, &b"lo"[..]));
+                server.reply(tag, &Rwrite::new(2)).await
+            }
+            other => panic!("expected Twrite, got {:?}", other),
+        }
+    };
+    let (flush, ()) = tokio::join!(flush, serve);
+    flush.unwrap();
+}
@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! RemoteFs against a scripted server.
+
+mod common;
+
+use common::{attach, connect, qid};
+use p9ds::proto::{Fcall, P9Version, QidType, Rgetattr, Rwalk};
+use p9kp::fs::RemoteFs;
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[tokio::test]
+async fn metadata_times() {
+    let (session, mut server) = connect();
+    let root = attach(&session, &mut server, P9Version::V2000L).await;
+    let fs = RemoteFs::new(root, 0).unwrap();
+
+    let metadata = fs.metadata("");
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Twalk(_))) => {
+                server.reply(tag, &Rwalk::new(vec![])).await
+            }
+            other => panic!("expected Twalk, got {:?}", other),
+        }
+        match server.recv().await {
+            Some((tag, Fcall::Tgetattr(t))) => {
+                // Accessed half a second before the epoch, and modified at
+                // a time with more than a second's worth of nanoseconds.
+                let attr = Rgetattr::new(
+                    t.request_mask,
+                    qid(QidType::FILE, 1),
+                    0o100644,
+                    0,
+                    0,
+                    1,
+                    0,
+                    0,
+                    4096,
+                    0,
+                    -1i64 as u64,
+                    500_000_000,
+                    0,
+                    1_000_000_000,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                );
+                server.reply(tag, &attr).await
+            }
+            other => panic!("expected Tgetattr, got {:?}", other),
+        }
+    };
+    let (metadata, ()) = tokio::join!(metadata, serve);
+    let metadata = metadata.unwrap();
+    assert_eq!(
+        metadata.accessed().unwrap(),
+        UNIX_EPOCH - Duration::from_millis(500)
+    );
+    assert!(metadata.modified().is_err());
+}
+
+#[tokio::test]
+async fn needs_9p2000l() {
+    let (session, mut server) = connect();
+    let root = attach(&session, &mut server, P9Version::V2000U).await;
+    let e = RemoteFs::new(root, 0).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+}
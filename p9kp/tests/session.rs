@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Session behaviour against a scripted server: tag multiplexing, responses
+//! coming back out of order, flushing abandoned requests and shutting the
+//! connection down once the session is dropped, and 9P2000.u errors.
+
+mod common;
+
+use common::{connect, TIMEOUT};
+use futures::future::join_all;
+use p9ds::errno::Errno;
+use p9ds::error::P9Error;
+use p9ds::proto::{Fcall, P9Version, RerrorU, Rflush, Rread, Tread};
+use std::collections::HashSet;
+
+#[tokio::test]
+async fn peer_sees_eof_after_drop() {
+    let (session, mut server) = connect();
+    let clone = session.clone();
+    drop(session);
+    drop(clone);
+    assert!(server.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn responses_out_of_order() {
+    let (session, mut server) = connect();
+    const N: u32 = 16;
+
+    let reads = join_all((0..N).map(|fid| {
+        let session = session.clone();
+        async move { session.request(&Tread::new(fid, 0, 1)).await }
+    }));
+    let serve = async {
+        let mut requests = Vec::new();
+        for _ in 0..N {
+            match server.recv().await {
+                Some((tag, Fcall::Tread(t))) => requests.push((tag, t.fid)),
+                other => panic!("expected Tread, got {:?}", other),
+            }
+        }
+        let tags: HashSet<u16> = requests.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags.len(), N as usize, "tags in use at once must differ");
+        // Answer each read with its own fid, last one first.
+        for (tag, fid) in requests.into_iter().rev() {
+            server.reply(tag, &Rread::new(vec![fid as u8])).await;
+        }
+    };
+    let (reads, ()) = tokio::join!(reads, serve);
+
+    for (fid, read) in reads.into_iter().enumerate() {
+        assert_eq!(read.unwrap().data, vec![fid as u8]);
+    }
+}
+
+#[tokio::test]
+async fn abandoned_request_is_flushed() {
+    let (session, mut server) = connect();
+
+    let tread = Tread::new(1, 0, 1);
+    let read = session.request(&tread);
+    let abandon = async {
+        // Give up on the read once the server has it.
+        tokio::select! {
+            _ = read => panic!("the read was never answered"),
+            request = server.recv() => request,
+        }
+    };
+    let oldtag = match abandon.await {
+        Some((tag, Fcall::Tread(_))) => tag,
+        other => panic!("expected Tread, got {:?}", other),
+    };
+    let flush = match server.recv().await {
+        Some((tag, Fcall::Tflush(t))) => {
+            assert_eq!(t.oldtag, oldtag);
+            tag
+        }
+        other => panic!("expected Tflush, got {:?}", other),
+    };
+    // A response that crosses the Tflush is dropped, and the session carries
+    // on once the flush is answered.
+    server.reply(oldtag, &Rread::new(vec![1])).await;
+    server.reply(flush, &Rflush::new()).await;
+
+    let tread = Tread::new(2, 0, 1);
+    let read = session.request(&tread);
+    let serve = async {
+        match server.recv().await {
+            Some((tag, Fcall::Tread(t))) => {
+                assert_eq!(t.fid, 2);
+                server.reply(tag, &Rread::new(vec![2])).await;
+            }
+            other => panic!("expected Tread, got {:?}", other),
+        }
+    };
+    let (read, ()) =
+        tokio::time::timeout(TIMEOUT, async { tokio::join!(read, serve) })
+            .await
+            .unwrap();
+    assert_eq!(read.unwrap().data, vec![2]);
+}
+
+#[tokio::test]
+async fn rerror_u_carries_errno() {
+    let (session, mut server) = connect();
+    let (negotiated, ()) = tokio::join!(
+        session.negotiate(0x10000),
+        server.agree(P9Version::V2000U)
+    );
+    assert_eq!(negotiated.unwrap().0, P9Version::V2000U);
+    assert_eq!(session.version(), P9Version::V2000U);
+
+    let tread = Tread::new(1, 0, 1);
+    let serve = async {
+        let (tag, _) = server.recv().await.unwrap();
+        let enoent = RerrorU::new("No such file or directory".into(), 2);
+        server.reply(tag, &enoent).await;
+    };
+    let (read, ()) = tokio::join!(session.request(&tread), serve);
+    assert!(matches!(read, Err(P9Error::Server(Errno::ENOENT))));
+}
+
+#[tokio::test]
+async fn requests_fail_once_server_hangs_up() {
+    let (session, server) = connect();
+    drop(server);
+    let read =
+        tokio::time::timeout(TIMEOUT, session.request(&Tread::new(1, 0, 1)))
+            .await
+            .unwrap();
+    assert!(matches!(read, Err(P9Error::NotConnected)));
+}
+
+#[tokio::test]
+async fn negotiate_rejects_msize_without_room_for_data() {
+    let (session, mut server) = connect();
+    let (negotiated, ()) =
+        tokio::join!(session.negotiate(16), server.agree(P9Version::V2000L));
+    match negotiated {
+        Err(P9Error::Protocol(e)) => {
+            assert_eq!(e, "msize 16 leaves no room for data")
+        }
+        other => panic!("expected a protocol error, got {:?}", other),
+    }
+    // The session keeps the msize it had, so a read has room for data.
+    assert!(session.max_read() > 0);
+}
@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! The vsock transport over the loopback CID. This needs a Linux kernel with
+//! vsock loopback support (the vsock_loopback module), so it is ignored by
+//! default. Run it with `cargo test -- --ignored`.
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{Server, TIMEOUT};
+use p9ds::proto::P9Version;
+use p9kp::session::Session;
+use p9kp::transport::StreamTransport;
+use p9kp::vsock::{self, VsockAddr, VMADDR_CID_LOCAL};
+use slog::{o, Discard, Logger};
+
+#[tokio::test]
+#[ignore = "needs vsock loopback"]
+async fn loopback() {
+    let mut listener = vsock::listen(libc::VMADDR_PORT_ANY).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let serve = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        Server::new(stream).agree(P9Version::V2000L).await;
+    };
+
+    let log = Logger::root(Discard, o!());
+    let connect = async {
+        let addr = VsockAddr::new(VMADDR_CID_LOCAL, port);
+        let transport = StreamTransport::vsock(addr, log.clone()).await?;
+        Session::new(Box::new(transport), log.clone())
+            .negotiate(0x10000)
+            .await
+    };
+    let (negotiated, ()) =
+        tokio::time::timeout(TIMEOUT, async { tokio::join!(connect, serve) })
+            .await
+            .expect("no vsock loopback connection");
+    assert_eq!(negotiated.unwrap().0, P9Version::V2000L);
+}